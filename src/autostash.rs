@@ -0,0 +1,235 @@
+use crate::{git, stack::StackConfig, validation};
+use anyhow::Result;
+use colored::Colorize;
+
+const STASH_MESSAGE: &str = "gh-flow autostash";
+
+/// Whether rewriting commands stash uncommitted changes: the `--autostash`
+/// flag if given, otherwise the `autostash` setting
+pub fn enabled(flag: Option<bool>) -> Result<bool> {
+    if let Some(flag) = flag {
+        return Ok(flag);
+    }
+    let settings = StackConfig::load()?.settings()?;
+    Ok(settings
+        .get("autostash")
+        .and_then(|(value, _)| value.as_bool())
+        .unwrap_or(false))
+}
+
+/// Run a command that rewrites branches. Uncommitted changes to tracked files
+/// either stop it up front or, with `autostash`, are stashed together with
+/// untracked files and restored on the original branch afterwards.
+pub fn run<T>(autostash: bool, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+    let original_branch = git::current_branch()?;
+
+    let stash = match validation::check_clean_worktree() {
+        Ok(()) => None,
+        Err(e) if !autostash => return Err(e),
+        Err(_) => {
+            let stash = push()?;
+            println!("{} Stashed uncommitted changes", "✓".green());
+            println!();
+            stash
+        }
+    };
+
+    let result = operation();
+    restore(&original_branch, stash.as_deref());
+    result
+}
+
+/// Stash the worktree and return the commit of the new entry, so it can be
+/// popped later even if other entries were stashed on top of it
+fn push() -> Result<Option<String>> {
+    let before = git::rev_parse("refs/stash");
+    git::run(&[
+        "stash",
+        "push",
+        "--include-untracked",
+        "--message",
+        STASH_MESSAGE,
+    ])?;
+    Ok(git::rev_parse("refs/stash").filter(|after| before.as_ref() != Some(after)))
+}
+
+/// The `stash@{n}` name of the entry whose commit is `commit`, if it is still stashed
+fn entry(commit: &str) -> Option<String> {
+    git::run(&["stash", "list", "--format=%gd %H"])
+        .ok()?
+        .lines()
+        .find_map(|line| {
+            let (name, hash) = line.split_once(' ')?;
+            (hash == commit).then(|| name.to_string())
+        })
+}
+
+/// Files left unmerged in the worktree
+fn conflicted_files() -> Vec<String> {
+    git::run(&["diff", "--name-only", "--diff-filter=U"])
+        .map(|out| out.lines().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// How to get the stashed changes back by hand
+fn pop_hint(commit: &str) -> String {
+    let name = entry(commit).unwrap_or_else(|| commit.to_string());
+    format!("`git stash pop {}`", name)
+}
+
+/// Go back to the branch the command started on and pop the stash
+fn restore(original_branch: &str, stash: Option<&str>) {
+    if validation::check_no_rebase_in_progress().is_err() {
+        if let Some(commit) = stash {
+            println!(
+                "{} Your changes are stashed as '{}'. Run {} on {} once the rebase is resolved.",
+                "⚠".yellow(),
+                STASH_MESSAGE,
+                pop_hint(commit),
+                original_branch.cyan()
+            );
+        }
+        return;
+    }
+
+    if git::current_branch().ok().as_deref() != Some(original_branch) {
+        if let Err(e) = git::run(&["checkout", "--quiet", original_branch]) {
+            println!(
+                "{} Could not return to {}: {}",
+                "⚠".yellow(),
+                original_branch.cyan(),
+                e
+            );
+            if let Some(commit) = stash {
+                println!(
+                    "  Your changes are stashed as '{}'; restore them with {}",
+                    STASH_MESSAGE,
+                    pop_hint(commit)
+                );
+            }
+            return;
+        }
+    }
+
+    let Some(commit) = stash else {
+        return;
+    };
+    let Some(name) = entry(commit) else {
+        println!(
+            "{} The '{}' stash entry is gone; your changes were not restored",
+            "⚠".yellow(),
+            STASH_MESSAGE
+        );
+        return;
+    };
+    match git::run(&["stash", "pop", "--quiet", &name]) {
+        Ok(_) => println!("{} Restored uncommitted changes", "✓".green()),
+        Err(e) => {
+            let conflicts = conflicted_files();
+            if !conflicts.is_empty() && entry(commit).is_some() {
+                println!(
+                    "{} Restoring your changes conflicted in {}. Resolve the conflicts, then run `git stash drop {}`; the changes stay in the stash until then.",
+                    "⚠".yellow(),
+                    conflicts.join(", "),
+                    name
+                );
+            } else {
+                println!(
+                    "{} Could not restore your changes: {}\n  They are stashed as '{}'; restore them with {}",
+                    "⚠".yellow(),
+                    e,
+                    STASH_MESSAGE,
+                    pop_hint(commit)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FlowError;
+    use crate::testing::Fixture;
+    use std::fs;
+
+    fn stash_subjects(repo: &Fixture) -> Vec<String> {
+        repo.git(&["stash", "list", "--format=%gs"])
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn dirty_worktree_stops_without_autostash() {
+        let repo = Fixture::new();
+        repo.commit_file("a.txt", "one\n");
+        fs::write("a.txt", "two\n").unwrap();
+
+        let error = run(false, || Ok(())).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<FlowError>(),
+            Some(FlowError::DirtyWorktree)
+        ));
+        assert!(stash_subjects(&repo).is_empty());
+    }
+
+    #[test]
+    fn restores_changes_on_the_original_branch() {
+        let repo = Fixture::new();
+        repo.commit_file("a.txt", "one\n");
+        fs::write("a.txt", "two\n").unwrap();
+        fs::write("new.txt", "untracked\n").unwrap();
+
+        run(true, || {
+            assert_eq!(git::run(&["status", "--porcelain"])?, "");
+            git::run(&["checkout", "--quiet", "-b", "other"])?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(git::current_branch().unwrap(), "main");
+        assert_eq!(fs::read_to_string("a.txt").unwrap(), "two\n");
+        assert_eq!(fs::read_to_string("new.txt").unwrap(), "untracked\n");
+        assert!(stash_subjects(&repo).is_empty());
+    }
+
+    #[test]
+    fn pops_its_own_entry_rather_than_the_newest() {
+        let repo = Fixture::new();
+        repo.commit_file("a.txt", "one\n");
+        repo.commit_file("b.txt", "one\n");
+        fs::write("a.txt", "mine\n").unwrap();
+
+        run(true, || {
+            fs::write("b.txt", "someone else's\n")?;
+            git::run(&["stash", "push", "--message", "other"])?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(fs::read_to_string("a.txt").unwrap(), "mine\n");
+        assert_eq!(fs::read_to_string("b.txt").unwrap(), "one\n");
+        assert_eq!(stash_subjects(&repo), vec!["On main: other"]);
+    }
+
+    #[test]
+    fn keeps_the_entry_when_restoring_conflicts() {
+        let repo = Fixture::new();
+        repo.commit_file("a.txt", "one\n");
+        fs::write("a.txt", "mine\n").unwrap();
+
+        run(true, || {
+            fs::write("a.txt", "rewritten\n")?;
+            git::run(&["commit", "--quiet", "-am", "Rewrite a"])?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(conflicted_files(), vec!["a.txt"]);
+        assert_eq!(
+            stash_subjects(&repo),
+            vec![format!("On main: {}", STASH_MESSAGE)]
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use crate::git;
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+/// Locations GitHub looks for a CODEOWNERS file, in priority order
+const CODEOWNERS_PATHS: &[&str] = &[".github/CODEOWNERS", "CODEOWNERS", "docs/CODEOWNERS"];
+
+struct Rule {
+    pattern: String,
+    owners: Vec<String>,
+}
+
+/// Parsed CODEOWNERS file
+pub struct CodeOwners {
+    rules: Vec<Rule>,
+}
+
+/// Owners suggested as reviewers for a set of files
+#[derive(Debug, Default)]
+pub struct Suggestion {
+    /// User logins (without `@`)
+    pub users: Vec<String>,
+    /// Teams as `org/team` (without `@`)
+    pub teams: Vec<String>,
+}
+
+impl Suggestion {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.teams.is_empty()
+    }
+}
+
+impl CodeOwners {
+    /// Load CODEOWNERS from the repository root, if there is one
+    pub fn load() -> Result<Option<Self>> {
+        let root = git::run(&["rev-parse", "--show-toplevel"])?;
+        for candidate in CODEOWNERS_PATHS {
+            let path = Path::new(&root).join(candidate);
+            if let Ok(content) = fs::read_to_string(&path) {
+                return Ok(Some(Self::parse(&content)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let rules = content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                let pattern = parts.next()?.to_string();
+                let owners = parts
+                    .take_while(|owner| !owner.starts_with('#'))
+                    .map(String::from)
+                    .collect();
+                Some(Rule { pattern, owners })
+            })
+            .collect();
+
+        Self { rules }
+    }
+
+    /// Owners of a single file. The last matching rule wins.
+    pub fn owners_of(&self, file: &str) -> &[String] {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| pattern_matches(&rule.pattern, file))
+            .map(|rule| rule.owners.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Reviewers for a set of changed files, skipping `exclude` (e.g. the PR author)
+    pub fn suggest<'a>(
+        &self,
+        files: impl IntoIterator<Item = &'a str>,
+        exclude: &str,
+    ) -> Suggestion {
+        let mut suggestion = Suggestion::default();
+
+        for file in files {
+            for owner in self.owners_of(file) {
+                // Owners can also be email addresses, which can't be requested as reviewers
+                let Some(login) = owner.strip_prefix('@') else {
+                    continue;
+                };
+                let list = if login.contains('/') {
+                    &mut suggestion.teams
+                } else {
+                    &mut suggestion.users
+                };
+                if login != exclude && !list.iter().any(|o| o == login) {
+                    list.push(login.to_string());
+                }
+            }
+        }
+
+        suggestion
+    }
+}
+
+/// Match a CODEOWNERS pattern (gitignore-style) against a repository path
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let dir_only = pattern.ends_with('/');
+    let trimmed = pattern.trim_end_matches('/');
+    // A slash anywhere but the end anchors the pattern to the repository root
+    let anchored = trimmed.contains('/');
+    let trimmed = trimmed.trim_start_matches('/');
+    // `docs/*` owns the files directly in docs/, not those in its subdirectories
+    let direct_children = anchored && trimmed.ends_with("/*");
+
+    if trimmed.is_empty() {
+        return false;
+    }
+
+    let pattern_segments: Vec<&str> = if anchored {
+        trimmed.split('/').collect()
+    } else {
+        std::iter::once("**").chain(trimmed.split('/')).collect()
+    };
+    let path_segments: Vec<&str> = path.split('/').collect();
+
+    // A pattern naming a directory owns everything below it
+    (1..=path_segments.len()).any(|len| {
+        let is_file = len == path_segments.len();
+        if (dir_only && is_file) || (direct_children && !is_file) {
+            return false;
+        }
+        match_segments(&pattern_segments, &path_segments[..len])
+    })
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_segments(rest, &path[skip..])),
+        Some((segment, rest)) => match path.split_first() {
+            Some((name, path_rest)) => {
+                glob_segment(segment.as_bytes(), name.as_bytes()) && match_segments(rest, path_rest)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Match one path segment against `*` / `?` wildcards
+fn glob_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| glob_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && glob_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && glob_segment(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extension_matches_at_any_depth() {
+        assert!(pattern_matches("*.js", "app.js"));
+        assert!(pattern_matches("*.js", "src/web/app.js"));
+        assert!(!pattern_matches("*.js", "app.json"));
+    }
+
+    #[test]
+    fn directory_owns_everything_below_it() {
+        assert!(pattern_matches("apps/", "apps/web/index.ts"));
+        assert!(pattern_matches("apps/", "src/apps/cli.rs"));
+        assert!(!pattern_matches("apps/", "apps"));
+        assert!(pattern_matches("/build/logs/", "build/logs/2024/out.log"));
+        assert!(!pattern_matches("/build/logs/", "src/build/logs/out.log"));
+    }
+
+    #[test]
+    fn star_matches_direct_children_only() {
+        assert!(pattern_matches("docs/*", "docs/getting-started.md"));
+        assert!(!pattern_matches(
+            "docs/*",
+            "docs/build-app/troubleshooting.md"
+        ));
+        assert!(!pattern_matches("docs/*", "src/docs/readme.md"));
+    }
+
+    #[test]
+    fn double_star_matches_any_number_of_directories() {
+        assert!(pattern_matches("**/logs", "logs/a.log"));
+        assert!(pattern_matches("**/logs", "deep/down/logs/a.log"));
+        assert!(pattern_matches("docs/**/api.md", "docs/api.md"));
+        assert!(pattern_matches("docs/**/api.md", "docs/v1/v2/api.md"));
+    }
+
+    #[test]
+    fn anchored_paths_match_from_the_root() {
+        assert!(pattern_matches("/Makefile", "Makefile"));
+        assert!(!pattern_matches("/Makefile", "sub/Makefile"));
+        assert!(pattern_matches("Makefile", "sub/Makefile"));
+        assert!(pattern_matches("src/lib?.rs", "src/lib1.rs"));
+        assert!(!pattern_matches("src/lib?.rs", "src/lib.rs"));
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let owners =
+            CodeOwners::parse("# comment\n*.rs @rust-team\n/src/git.rs @alice @org/git # inline\n");
+        assert_eq!(owners.owners_of("src/main.rs"), ["@rust-team"]);
+        assert_eq!(owners.owners_of("src/git.rs"), ["@alice", "@org/git"]);
+        assert!(owners.owners_of("README.md").is_empty());
+    }
+
+    #[test]
+    fn suggest_splits_users_and_teams_and_skips_the_author() {
+        let owners = CodeOwners::parse("* @alice @bob @org/core dev@example.com\n");
+        let suggestion = owners.suggest(["a.rs", "b.rs"], "bob");
+        assert_eq!(suggestion.users, vec!["alice"]);
+        assert_eq!(suggestion.teams, vec!["org/core"]);
+    }
+}
@@ -1,9 +1,89 @@
-use crate::{git, github, stack::StackConfig};
+use crate::{
+    autostash, git,
+    github::{self, PullRequest},
+    stack::{BranchInfo, StackConfig},
+};
 use anyhow::{Context, Result};
 use colored::Colorize;
+use std::collections::HashMap;
 
-pub fn run() -> Result<()> {
-    println!("{}", "Adopting existing PR into stack...".green().bold());
+/// Fetch the PR head into a local branch if there isn't one yet
+fn ensure_local_branch(pr: &PullRequest) -> Result<()> {
+    if git::branch_exists(&pr.head_ref)? {
+        return Ok(());
+    }
+
+    let remote = git::upstream_remote();
+    if pr.cross_repository {
+        // Fork branches aren't on our remote, but GitHub keeps every PR head there
+        git::run(&[
+            "fetch",
+            &remote,
+            &format!("pull/{}/head:{}", pr.number, pr.head_ref),
+        ])?;
+    } else {
+        git::run(&["fetch", &remote, &pr.head_ref])?;
+        git::run(&[
+            "branch",
+            "--track",
+            &pr.head_ref,
+            &format!("{}/{}", remote, pr.head_ref),
+        ])?;
+    }
+    println!("  {} Fetched {}", "✓".green(), pr.head_ref.cyan());
+    Ok(())
+}
+
+fn rebase_failed(branch: &str, e: anyhow::Error) -> anyhow::Error {
+    anyhow::anyhow!(
+        "Failed to rebase {}: {}\nResolve the conflicts, run `git rebase --continue`, then `gh flow sync`",
+        branch,
+        e
+    )
+}
+
+/// Insert `branch` right above its parent, in `chain` and in the config.
+/// Returns the branch that followed the parent in the chain, which now
+/// follows `branch`.
+fn insert(config: &mut StackConfig, chain: &mut Vec<String>, branch: BranchInfo) -> Option<String> {
+    let index = config
+        .branches
+        .iter()
+        .position(|b| b.name == branch.parent)
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    let above = chain
+        .iter()
+        .position(|b| *b == branch.parent)
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    let moved = chain
+        .get(above)
+        .and_then(|child| config.get_branch_mut(child))
+        .filter(|child| child.parent == branch.parent)
+        .map(|child| {
+            child.parent = branch.name.clone();
+            child.name.clone()
+        });
+    chain.insert(above, branch.name.clone());
+    config.branches.insert(index, branch);
+    moved
+}
+
+pub fn run(
+    pr_numbers: &[u32],
+    after: Option<&str>,
+    rebase: bool,
+    autostash: Option<bool>,
+) -> Result<()> {
+    autostash::run(autostash::enabled(autostash)?, || {
+        adopt(pr_numbers, after, rebase)
+    })
+}
+
+fn adopt(pr_numbers: &[u32], after: Option<&str>, rebase: bool) -> Result<()> {
+    println!("{}", "Adopting existing PRs into stack...".green().bold());
     println!();
 
     // Load configuration
@@ -13,58 +93,231 @@ pub fn run() -> Result<()> {
         anyhow::bail!("No stack found. Run `gh flow init` first.");
     }
 
-    // Get current branch
-    let current_branch = git::current_branch()?;
-    println!("Current branch: {}", current_branch.cyan());
+    let original_branch = git::current_branch()?;
 
-    // Check if branch already in stack
-    if config.branches.iter().any(|b| b.name == current_branch) {
-        anyhow::bail!("Branch '{}' is already in the stack", current_branch);
-    }
+    // Without PR numbers, adopt the PR of the current branch
+    let prs = if pr_numbers.is_empty() {
+        println!("Current branch: {}", original_branch.cyan());
+        let pr = github::get_pr(&original_branch)?.context(format!(
+            "No PR found for branch '{}'. Create a PR first.",
+            original_branch
+        ))?;
+        vec![pr]
+    } else {
+        pr_numbers
+            .iter()
+            .map(|&number| github::get_pr_by_number(number))
+            .collect::<Result<Vec<_>>>()?
+    };
 
-    // Check if PR exists for this branch
-    let pr = github::get_pr(&current_branch)?
-        .context(format!("No PR found for branch '{}'. Create a PR first.", current_branch))?;
+    // Tip of each branch's current parent, to rebase children after inserting a branch below them
+    let old_bases: HashMap<String, String> = config
+        .branches
+        .iter()
+        .filter_map(|b| git::rev_parse(&b.parent).map(|sha| (b.name.clone(), sha)))
+        .collect();
 
-    println!("Found PR #{}: {}", pr.number, pr.title);
-    println!("Current base: {}", pr.base_ref.yellow());
+    // The chain the PRs join: the one through --after, or the current branch's
+    let chain = match after {
+        Some(branch) if branch != config.base_branch && config.get_branch(branch).is_none() => {
+            anyhow::bail!("Branch '{}' is not in the stack", branch)
+        }
+        Some(branch) if branch != config.base_branch => config.chain_through(branch),
+        _ => config.current_stack()?.branches,
+    };
+    let mut chain: Vec<String> = chain.into_iter().map(|b| b.name).collect();
 
-    // Determine parent branch (last branch in stack, or base_branch if empty)
-    let parent_branch = if let Some(last) = config.branches.last() {
-        last.name.clone()
-    } else {
-        config.base_branch.clone()
+    let mut parent = match after {
+        Some(branch) => branch.to_string(),
+        // Append at the top of the chain, or on the base branch if there is none
+        None => chain
+            .last()
+            .cloned()
+            .unwrap_or_else(|| config.base_branch.clone()),
     };
+    let mut adopted: Vec<(String, u32)> = Vec::new();
+    let mut reparented: Vec<String> = Vec::new();
+
+    for pr in &prs {
+        let name = pr.head_ref.clone();
+        if config.get_branch(&name).is_some() {
+            anyhow::bail!("Branch '{}' is already in the stack", name);
+        }
 
-    println!("New base: {}", parent_branch.green());
+        println!("Found PR #{}: {}", pr.number, pr.title);
+        ensure_local_branch(pr)?;
+
+        let moved = insert(
+            &mut config,
+            &mut chain,
+            BranchInfo {
+                name: name.clone(),
+                parent: parent.clone(),
+                pr_number: Some(pr.number),
+                pushed_sha: None,
+            },
+        );
+        if let Some(child) = moved.filter(|child| !reparented.contains(child)) {
+            reparented.push(child);
+        }
+
+        // Update PR base if different
+        if pr.base_ref != parent {
+            print!(
+                "  Updating PR #{} base: {} → {} ... ",
+                pr.number,
+                pr.base_ref.yellow(),
+                parent.green()
+            );
+            github::update_pr_base(pr.number, &parent)?;
+            println!("{}", "✓".green());
+        }
+
+        adopted.push((name.clone(), pr.number));
+        parent = name;
+    }
     println!();
 
-    // Update PR base if different
-    if pr.base_ref != parent_branch {
-        print!("Updating PR base... ");
-        github::update_pr_base(pr.number, &parent_branch)?;
-        println!("{}", "✓".green());
-    } else {
-        println!("PR base already correct.");
+    // Save before rebasing, so a conflict doesn't lose the new stack order
+    config.save().context("Failed to save configuration")?;
+
+    if rebase {
+        println!("{}", "Rebasing adopted branches...".cyan());
+        for (name, _) in &adopted {
+            let new_parent = config
+                .get_branch(name)
+                .map(|b| b.parent.clone())
+                .unwrap_or_default();
+            git::rebase_branch(&new_parent, name).map_err(|e| rebase_failed(name, e))?;
+            println!("  {} {} onto {}", "✓".green(), name.cyan(), new_parent);
+        }
+        println!();
     }
 
-    // Add branch to stack
-    config.add_branch(current_branch.clone(), parent_branch.clone());
-    if let Some(branch_info) = config.branches.iter_mut().find(|b| b.name == current_branch) {
-        branch_info.pr_number = Some(pr.number);
+    // Rebase everything above the insertion point onto its new parent
+    if let Some(first) = reparented.first() {
+        println!("{}", "Rebasing branches above...".cyan());
+        let start = chain.iter().position(|b| b == first).unwrap_or(0);
+        for branch in chain[start..].iter().filter_map(|b| config.get_branch(b)) {
+            let Some(old_base) = old_bases.get(&branch.name) else {
+                continue;
+            };
+            git::rebase_onto(&branch.parent, old_base, &branch.name)
+                .map_err(|e| rebase_failed(&branch.name, e))?;
+            println!(
+                "  {} {} onto {}",
+                "✓".green(),
+                branch.name.cyan(),
+                branch.parent
+            );
+
+            if reparented.contains(&branch.name) {
+                if let Some(pr_number) = branch.pr_number {
+                    github::update_pr_base(pr_number, &branch.parent)?;
+                    println!(
+                        "  {} PR #{} base → {}",
+                        "✓".green(),
+                        pr_number,
+                        branch.parent
+                    );
+                }
+            }
+        }
+        println!();
     }
 
-    // Save configuration
-    config.save().context("Failed to save configuration")?;
+    if rebase || !reparented.is_empty() {
+        git::run(&["checkout", &original_branch])?;
+    }
 
-    println!();
-    println!(
-        "{} Branch '{}' adopted into stack (PR #{})",
-        "✓".green().bold(),
-        current_branch.cyan(),
-        pr.number
-    );
-    println!("  Parent: {}", parent_branch);
+    for (name, number) in &adopted {
+        println!(
+            "{} Branch '{}' adopted into stack (PR #{})",
+            "✓".green().bold(),
+            name.cyan(),
+            number
+        );
+    }
+    if rebase || !reparented.is_empty() {
+        println!("  Run `gh flow push` to update the remote branches");
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_stacks() -> StackConfig {
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        for (name, parent) in [("a1", "main"), ("a2", "a1"), ("b1", "main"), ("b2", "b1")] {
+            config.add_branch(name.to_string(), parent.to_string());
+        }
+        config
+    }
+
+    fn branch(name: &str, parent: &str) -> BranchInfo {
+        BranchInfo {
+            name: name.to_string(),
+            parent: parent.to_string(),
+            pr_number: None,
+            pushed_sha: None,
+        }
+    }
+
+    fn pairs(config: &StackConfig) -> Vec<(&str, &str)> {
+        config
+            .branches
+            .iter()
+            .map(|b| (b.name.as_str(), b.parent.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn inserts_mid_chain_and_moves_the_branch_above() {
+        let mut config = two_stacks();
+        let mut chain = vec!["b1".to_string(), "b2".to_string()];
+
+        let moved = insert(&mut config, &mut chain, branch("x", "b1"));
+
+        assert_eq!(moved.as_deref(), Some("b2"));
+        assert_eq!(chain, vec!["b1", "x", "b2"]);
+        assert_eq!(
+            pairs(&config),
+            vec![
+                ("a1", "main"),
+                ("a2", "a1"),
+                ("b1", "main"),
+                ("x", "b1"),
+                ("b2", "x")
+            ]
+        );
+    }
+
+    #[test]
+    fn appends_to_the_top_of_the_chain_only() {
+        let mut config = two_stacks();
+        let mut chain = vec!["a1".to_string(), "a2".to_string()];
+
+        assert_eq!(insert(&mut config, &mut chain, branch("x", "a2")), None);
+        assert_eq!(chain, vec!["a1", "a2", "x"]);
+        assert_eq!(config.get_branch("b1").unwrap().parent, "main");
+        assert_eq!(config.get_branch("x").unwrap().parent, "a2");
+    }
+
+    #[test]
+    fn inserting_on_the_base_moves_the_bottom_of_the_chain() {
+        let mut config = two_stacks();
+        let mut chain = vec!["b1".to_string(), "b2".to_string()];
+
+        let moved = insert(&mut config, &mut chain, branch("x", "main"));
+
+        assert_eq!(moved.as_deref(), Some("b1"));
+        assert_eq!(config.get_branch("b1").unwrap().parent, "x");
+        assert_eq!(config.get_branch("a1").unwrap().parent, "main");
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{
+    settings::{self, Scope},
+    stack::StackConfig,
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+/// Layer `config set` and `config unset` write to
+pub fn scope(global: bool, repo: bool) -> Scope {
+    if global {
+        Scope::Global
+    } else if repo {
+        Scope::Repo
+    } else {
+        Scope::User
+    }
+}
+
+pub fn get(name: &str, show_origin: bool) -> Result<()> {
+    settings::key(name)?;
+    let config = StackConfig::load().context("Failed to load configuration")?;
+    let settings = config.settings()?;
+    let (value, origin) = settings.get(name).context("Setting has no value")?;
+
+    if show_origin {
+        println!("{}\t{}", origin, settings::display_value(value));
+    } else {
+        println!("{}", settings::display_value(value));
+    }
+    Ok(())
+}
+
+pub fn set(name: &str, raw: Option<&str>, scope: Scope) -> Result<()> {
+    let key = settings::key(name)?;
+    let value = raw.map(|raw| key.parse(raw)).transpose()?;
+
+    let location = match scope {
+        Scope::User => {
+            let mut config = StackConfig::load().context("Failed to load configuration")?;
+            config.set_setting(name, value.clone())?;
+            config.save().context("Failed to save configuration")?;
+            "your settings for this repository".to_string()
+        }
+        _ => settings::write_file(scope, name, value.as_ref())?
+            .display()
+            .to_string(),
+    };
+
+    match &value {
+        Some(value) => println!(
+            "{} Set {} to {} in {}",
+            "✓".green(),
+            name.cyan(),
+            settings::display_value(value).cyan(),
+            location
+        ),
+        None => println!("{} Removed {} from {}", "✓".green(), name.cyan(), location),
+    }
+
+    // Tell why the new value may not take effect
+    let config = StackConfig::load().context("Failed to load configuration")?;
+    if let Some((_, origin)) = config.settings()?.get(name) {
+        if value.is_some() && origin.scope() != Some(scope) {
+            println!("  {} Overridden by {}", "⚠".yellow(), origin);
+        }
+    }
+    Ok(())
+}
+
+pub fn list(show_origin: bool) -> Result<()> {
+    let config = StackConfig::load().context("Failed to load configuration")?;
+    let settings = config.settings()?;
+
+    for key in settings::KEYS {
+        let Some((value, origin)) = settings.get(key.name) else {
+            continue;
+        };
+        let line = format!("{} = {}", key.name, settings::display_value(value));
+        let note = if show_origin {
+            origin.to_string()
+        } else {
+            format!("# {}", key.description)
+        };
+        println!("{:<40} {}", line, note.dimmed());
+    }
+
+    for (name, origin) in &settings.unknown {
+        println!("{} Unknown setting {} in {}", "⚠".yellow(), name, origin);
+    }
+    Ok(())
+}
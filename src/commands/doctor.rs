@@ -0,0 +1,511 @@
+use super::push;
+use crate::{
+    autostash, git,
+    github::{self, PullRequest},
+    settings::Settings,
+    stack::StackConfig,
+    storage::Storage,
+    validation,
+};
+use anyhow::{bail, Result};
+use colored::Colorize;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::process::Command;
+
+/// Problems found so far, and whether to repair them
+struct Report {
+    fix: bool,
+    problems: usize,
+    fixed: usize,
+}
+
+impl Report {
+    fn ok(&self, message: &str) {
+        println!("  {} {}", "✓".green(), message);
+    }
+
+    /// Record a problem `--fix` can repair. Returns true if it should be repaired now.
+    fn problem(&mut self, message: &str, fix: &str) -> bool {
+        self.problems += 1;
+        println!("  {} {}", "✗".red(), message);
+        if !self.fix {
+            println!("    {} {}", "fix:".dimmed(), fix.dimmed());
+        }
+        self.fix
+    }
+
+    /// Record a problem that has to be repaired by hand
+    fn manual(&mut self, message: &str, suggestion: &str) {
+        self.problems += 1;
+        println!("  {} {}", "✗".red(), message);
+        println!("    {} {}", "→".yellow(), suggestion);
+    }
+
+    fn fixed(&mut self, result: Result<String>) {
+        match result {
+            Ok(message) => {
+                self.fixed += 1;
+                println!("    {} {}", "✓".green(), message);
+            }
+            Err(e) => println!("    {} {:#}", "✗".red(), e),
+        }
+    }
+}
+
+/// First line of `<tool> --version`, or None if the tool can't be run
+fn tool_version(tool: &str) -> Option<String> {
+    let output = Command::new(tool).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+/// Check git and gh. Returns whether GitHub can be queried.
+fn check_tools(report: &mut Report) -> bool {
+    match tool_version("git") {
+        Some(version) => report.ok(&version),
+        None => report.manual(
+            "git is not installed",
+            "Install git from https://git-scm.com",
+        ),
+    }
+
+    let Some(version) = tool_version("gh") else {
+        report.manual(
+            "GitHub CLI (gh) is not installed",
+            "Install it from https://cli.github.com",
+        );
+        return false;
+    };
+    report.ok(&version);
+
+    if validation::check_gh_auth().is_err() {
+        report.manual("GitHub CLI is not authenticated", "Run: gh auth login");
+        return false;
+    }
+    report.ok("GitHub CLI authenticated");
+    true
+}
+
+/// Move an unreadable config file aside and rediscover the stack
+fn rediscover() -> Result<String> {
+    let base = Settings::resolve(&BTreeMap::new())?
+        .str("base-branch")
+        .to_string();
+    let config = StackConfig::discover(&base)?;
+
+    let path = StackConfig::config_path()?;
+    let backup = path.with_extension("json.broken");
+    fs::rename(&path, &backup)?;
+    config.save()?;
+    Ok(format!(
+        "Rediscovered {} branches; the old file is at {}",
+        config.branches.len(),
+        backup.display()
+    ))
+}
+
+fn check_config(report: &mut Report) -> Option<StackConfig> {
+    if let Err(e) = Settings::resolve(&BTreeMap::new()) {
+        report.manual(
+            &format!("Settings can't be read: {:#}", e),
+            "Fix the settings file named above",
+        );
+        return None;
+    }
+
+    match StackConfig::load() {
+        Ok(config) => {
+            report.ok("Stack configuration readable");
+            Some(config)
+        }
+        Err(e) => {
+            let message = format!("Stack configuration can't be read: {:#}", e);
+            if Storage::current() == Storage::Refs {
+                report.manual(&message, "Run `gh flow init` to rediscover the stack");
+                return None;
+            }
+            if report.problem(
+                &message,
+                "move it aside and rediscover the stack from git history",
+            ) {
+                report.fixed(rediscover());
+                return StackConfig::load().ok();
+            }
+            None
+        }
+    }
+}
+
+fn check_missing_branches(report: &mut Report, config: &mut StackConfig) -> Result<()> {
+    let names: Vec<String> = config.branches.iter().map(|b| b.name.clone()).collect();
+    let mut missing = false;
+    for name in names {
+        if git::branch_exists(&name)? {
+            continue;
+        }
+        missing = true;
+        if report.problem(
+            &format!("{} is tracked but no longer exists", name.cyan()),
+            "remove it from the stack",
+        ) {
+            config.remove_branch(&name);
+            report.fixed(Ok(format!("Removed {} from the stack", name)));
+        }
+    }
+    if !missing {
+        report.ok("All stack branches exist");
+    }
+    Ok(())
+}
+
+/// Drop `name` from the stack, moving its children onto its parent. Records
+/// the tip it had as their fork point, so only their own commits get rebased.
+fn drop_branch(config: &mut StackConfig, name: &str, fork_points: &mut HashMap<String, String>) {
+    let tip = git::rev_parse(name);
+    for child in config.remove_branch(name) {
+        if let Some(tip) = &tip {
+            fork_points.insert(child, tip.clone());
+        }
+    }
+}
+
+/// Check the PRs of the stack. Returns the fork points of the branches whose
+/// parent was removed.
+fn check_prs(report: &mut Report, config: &mut StackConfig) -> HashMap<String, String> {
+    let mut prs: HashMap<String, PullRequest> = HashMap::new();
+    for branch in &config.branches {
+        let Some(number) = branch.pr_number else {
+            continue;
+        };
+        match github::get_pr_by_number(number) {
+            Ok(pr) => {
+                prs.insert(branch.name.clone(), pr);
+            }
+            Err(e) => report.manual(
+                &format!(
+                    "Could not look up PR #{} of {}: {}",
+                    number,
+                    branch.name.cyan(),
+                    e
+                ),
+                "Check the PR still exists, then run `gh flow adopt` to track the right one",
+            ),
+        }
+    }
+
+    // Merged and closed PRs first: removing them changes the parents of the rest
+    let mut fork_points = HashMap::new();
+    let mut stale = false;
+    let names: Vec<String> = config.branches.iter().map(|b| b.name.clone()).collect();
+    for name in &names {
+        let Some(pr) = prs.get(name).filter(|pr| pr.state != "OPEN") else {
+            continue;
+        };
+        stale = true;
+        if report.problem(
+            &format!(
+                "PR #{} of {} is {} but still tracked",
+                pr.number,
+                name.cyan(),
+                pr.state.to_lowercase()
+            ),
+            "remove it from the stack",
+        ) {
+            drop_branch(config, name, &mut fork_points);
+            report.fixed(Ok(format!("Removed {} from the stack", name)));
+        }
+    }
+    if !stale {
+        report.ok("No merged or closed PRs tracked");
+    }
+
+    let mut mismatched = false;
+    for branch in &config.branches {
+        let Some(pr) = prs.get(&branch.name).filter(|pr| pr.state == "OPEN") else {
+            continue;
+        };
+        if pr.base_ref == branch.parent {
+            continue;
+        }
+        mismatched = true;
+        if report.problem(
+            &format!(
+                "PR #{} targets {}, but {} is based on {}",
+                pr.number,
+                pr.base_ref.yellow(),
+                branch.name.cyan(),
+                branch.parent.green()
+            ),
+            &format!("retarget the PR to {}", branch.parent),
+        ) {
+            report.fixed(
+                github::update_pr_base(pr.number, &branch.parent)
+                    .map(|_| format!("PR #{} now targets {}", pr.number, branch.parent)),
+            );
+        }
+    }
+    if !mismatched {
+        report.ok("PR bases match the stack");
+    }
+    fork_points
+}
+
+/// Check every branch contains its parent. `fork_points` has the old parent tip
+/// of branches whose parent was removed from the stack.
+fn check_ancestry(
+    report: &mut Report,
+    config: &StackConfig,
+    fork_points: &HashMap<String, String>,
+) -> Result<()> {
+    let original_branch = git::current_branch()?;
+    let mut rebased = false;
+    let mut diverged = false;
+
+    // Tips of the branches rebased so far, from before the rebase
+    let mut old_tips: HashMap<String, String> = HashMap::new();
+
+    for branch in &config.branches {
+        let Some(tip) = git::rev_parse(&branch.name) else {
+            continue;
+        };
+        if git::rev_parse(&branch.parent).is_none() {
+            continue;
+        }
+        if git::is_ancestor(&branch.parent, &branch.name) {
+            continue;
+        }
+        diverged = true;
+        if report.problem(
+            &format!(
+                "{} does not contain the tip of {}",
+                branch.name.cyan(),
+                branch.parent
+            ),
+            &format!("rebase it onto {}", branch.parent),
+        ) {
+            rebased = true;
+            // Above a removed or rebased parent, only move the branch's own
+            // commits: the parent's may have landed squashed
+            let old_base = fork_points
+                .get(&branch.name)
+                .or_else(|| old_tips.get(&branch.parent));
+            let result = match old_base {
+                Some(old_base) => git::rebase_onto(&branch.parent, old_base, &branch.name),
+                None => git::rebase_branch(&branch.parent, &branch.name),
+            };
+            if result.is_ok() {
+                old_tips.insert(branch.name.clone(), tip);
+            } else if validation::check_no_rebase_in_progress().is_err() {
+                // --fix only runs without a rebase in progress, so this one is ours
+                let _ = git::run(&["rebase", "--abort"]);
+            }
+            report.fixed(result.map(|_| format!("Rebased {} onto {}", branch.name, branch.parent)));
+        }
+    }
+
+    if rebased {
+        git::run(&["checkout", &original_branch])?;
+    }
+    if !diverged {
+        report.ok("Every branch descends from its parent");
+    }
+    Ok(())
+}
+
+fn check_duplicates(report: &mut Report, config: &mut StackConfig) {
+    let mut at_commit: HashMap<String, Vec<String>> = HashMap::new();
+    for branch in &config.branches {
+        if let Some(sha) = git::rev_parse(&branch.name) {
+            at_commit.entry(sha).or_default().push(branch.name.clone());
+        }
+    }
+
+    let mut duplicates: Vec<Vec<String>> = at_commit
+        .into_values()
+        .filter(|names| names.len() > 1)
+        .collect();
+    duplicates.sort();
+    if duplicates.is_empty() {
+        report.ok("No two branches point to the same commit");
+        return;
+    }
+
+    for names in duplicates {
+        // Keep the branch with a PR, or the bottom-most one
+        let keep = names
+            .iter()
+            .find(|name| {
+                config
+                    .get_branch(name)
+                    .is_some_and(|b| b.pr_number.is_some())
+            })
+            .unwrap_or(&names[0])
+            .clone();
+        let drop: Vec<&String> = names.iter().filter(|name| **name != keep).collect();
+        let drop_list = drop
+            .iter()
+            .map(|name| name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if report.problem(
+            &format!("{} point to the same commit", names.join(", ").cyan()),
+            &format!(
+                "keep {} in the stack and drop {} (the branches are not deleted)",
+                keep, drop_list
+            ),
+        ) {
+            for name in &drop {
+                config.remove_branch(name);
+            }
+            report.fixed(Ok(format!("Dropped {} from the stack", drop_list)));
+        }
+    }
+}
+
+/// Check the stack's branches and PRs, repairing them when `report.fix` is set
+fn check_stack(report: &mut Report, config: &mut StackConfig, github_ready: bool) -> Result<()> {
+    println!("{}", "Stack".cyan());
+    check_missing_branches(report, config)?;
+    let fork_points = if github_ready {
+        check_prs(report, config)
+    } else {
+        println!(
+            "  {} PR checks skipped, GitHub CLI is not ready",
+            "⚠".yellow()
+        );
+        HashMap::new()
+    };
+    check_ancestry(report, config, &fork_points)?;
+    check_duplicates(report, config);
+    println!();
+    Ok(())
+}
+
+pub fn run(fix: bool, autostash: Option<bool>) -> Result<()> {
+    println!("{}", "Checking gh-flow setup...".green().bold());
+    println!();
+
+    let mut report = Report {
+        fix,
+        problems: 0,
+        fixed: 0,
+    };
+
+    println!("{}", "Tools".cyan());
+    let github_ready = check_tools(&mut report);
+    println!();
+
+    if validation::check_git_repo().is_err() {
+        report.manual(
+            "Not inside a git repository",
+            "Run this command inside a git repository",
+        );
+        return summarize(&report);
+    }
+
+    println!("{}", "Configuration".cyan());
+    let config = check_config(&mut report);
+    println!();
+
+    let Some(mut config) = config else {
+        return summarize(&report);
+    };
+    if config.branches.is_empty() {
+        println!(
+            "{}",
+            "No branches in stack. Run `gh flow init` first.".yellow()
+        );
+        println!();
+        return summarize(&report);
+    }
+    let saved = config.clone();
+
+    if fix {
+        autostash::run(autostash::enabled(autostash)?, || {
+            check_stack(&mut report, &mut config, github_ready)
+        })?;
+    } else {
+        check_stack(&mut report, &mut config, github_ready)?;
+    }
+
+    if config != saved {
+        config.save()?;
+        push::share_stack();
+    }
+
+    summarize(&report)
+}
+
+fn problems(count: usize) -> String {
+    format!("{} problem{}", count, if count == 1 { "" } else { "s" })
+}
+
+/// Print the outcome. Fails while problems remain, so scripts can tell.
+fn summarize(report: &Report) -> Result<()> {
+    if report.problems == 0 {
+        println!("{}", "✓ No problems found".green().bold());
+    } else if !report.fix {
+        bail!(
+            "{} found. Run `gh flow doctor --fix` to repair them.",
+            problems(report.problems)
+        );
+    } else if report.fixed < report.problems {
+        bail!("Fixed {} of {}", report.fixed, problems(report.problems));
+    } else {
+        println!(
+            "{}",
+            format!("✓ Fixed {}", problems(report.problems))
+                .green()
+                .bold()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn rebases_above_a_squash_merged_branch_without_replaying_it() {
+        let repo = Fixture::new();
+        repo.branch("a");
+        repo.commit_file("a.txt", "1\n");
+        repo.commit_file("a.txt", "2\n");
+        repo.branch("b");
+        let own = repo.commit_file("b.txt", "b\n");
+        repo.git(&["checkout", "--quiet", "main"]);
+        repo.commit_file("a.txt", "2\n");
+
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        config.add_branch("a".to_string(), "main".to_string());
+        config.add_branch("b".to_string(), "a".to_string());
+
+        let mut fork_points = HashMap::new();
+        drop_branch(&mut config, "a", &mut fork_points);
+        assert_eq!(config.get_branch("b").unwrap().parent, "main");
+
+        let mut report = Report {
+            fix: true,
+            problems: 0,
+            fixed: 0,
+        };
+        check_ancestry(&mut report, &config, &fork_points).unwrap();
+
+        assert_eq!((report.problems, report.fixed), (1, 1));
+        assert_eq!(git::current_branch().unwrap(), "main");
+        assert!(git::is_ancestor("main", "b"));
+        let subjects = git::commit_subjects("main..b").unwrap();
+        assert_eq!(subjects.len(), 1);
+        assert_ne!(git::rev_parse("b").unwrap(), own);
+    }
+}
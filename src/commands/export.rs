@@ -0,0 +1,64 @@
+use crate::{
+    git,
+    schema::{Format, StackExport},
+    stack::StackConfig,
+    tools::{self, ExportTool},
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::fs;
+use std::path::Path;
+
+fn export_to_tool(config: &StackConfig, to: ExportTool) -> Result<()> {
+    let pairs: Vec<(String, String)> = config
+        .branches
+        .iter()
+        .map(|b| (b.name.clone(), b.parent.clone()))
+        .collect();
+
+    match to {
+        ExportTool::GitTown => {
+            tools::export_git_town(&config.base_branch, &pairs)?;
+            println!(
+                "{} Recorded {} branches as git-town parent branches",
+                "✓".green(),
+                pairs.len()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Export the stack HEAD is on to another tool, or as a versioned JSON/YAML
+/// document written to `output` (stdout when not given)
+pub fn run(to: Option<ExportTool>, format: Format, output: Option<&Path>) -> Result<()> {
+    let config = StackConfig::load()
+        .context("Failed to load configuration")?
+        .current_stack()?;
+
+    if config.branches.is_empty() {
+        anyhow::bail!("No branches in stack. Run `gh flow init` first.");
+    }
+
+    if let Some(to) = to {
+        return export_to_tool(&config, to);
+    }
+
+    let document = StackExport::from_config(&config, git::rev_parse).render(format)?;
+    match output {
+        Some(path) => {
+            fs::write(path, document)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!(
+                "{} Exported {} branches to {}",
+                "✓".green(),
+                config.branches.len(),
+                path.display()
+            );
+        }
+        None => print!("{}", document),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use crate::{
+    git,
+    github::{self, PullRequest},
+    stack::{BranchInfo, StackConfig},
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+/// Walk PR bases down to the bottom of the stack
+fn walk_down(start: PullRequest) -> Result<Vec<PullRequest>> {
+    let mut stack = vec![start];
+    loop {
+        let base = stack[0].base_ref.clone();
+        match github::get_pr(&base)? {
+            Some(pr) if !stack.iter().any(|p| p.number == pr.number) => stack.insert(0, pr),
+            _ => return Ok(stack),
+        }
+    }
+}
+
+/// Follow the PRs based on each head up to the top of the stack
+fn walk_up(stack: &mut Vec<PullRequest>) -> Result<()> {
+    while let Some(top) = stack.last() {
+        let head = top.head_ref.clone();
+        let mut children: Vec<PullRequest> = github::list_prs_with_base(&head)?
+            .into_iter()
+            .filter(|pr| !pr.cross_repository && !stack.iter().any(|p| p.number == pr.number))
+            .collect();
+
+        if children.is_empty() {
+            break;
+        }
+        if children.len() > 1 {
+            println!(
+                "  {} {} PRs are based on {}, following #{}",
+                "⚠".yellow(),
+                children.len(),
+                head.cyan(),
+                children[0].number
+            );
+        }
+        stack.push(children.remove(0));
+    }
+    Ok(())
+}
+
+/// What happened to a local branch when fetching the stack
+enum Tracked {
+    Created,
+    UpToDate,
+    FastForwarded,
+    /// The local branch has commits the remote doesn't; it was left alone
+    Diverged,
+}
+
+/// Create or fast-forward a local branch tracking `remote/branch`
+fn track_branch(remote: &str, branch: &str) -> Result<Tracked> {
+    let remote_ref = format!("{}/{}", remote, branch);
+    let theirs =
+        git::rev_parse(&remote_ref).with_context(|| format!("{} was not fetched", remote_ref))?;
+
+    let Some(ours) = git::rev_parse(branch) else {
+        git::run(&["branch", "--track", branch, &remote_ref])?;
+        return Ok(Tracked::Created);
+    };
+
+    if ours == theirs {
+        return Ok(Tracked::UpToDate);
+    }
+    if !git::is_ancestor(&ours, &theirs) {
+        return Ok(Tracked::Diverged);
+    }
+
+    if git::current_branch()? == branch {
+        git::run(&["merge", "--ff-only", &remote_ref])?;
+    } else {
+        git::run(&["branch", "-f", branch, &remote_ref])?;
+    }
+    Ok(Tracked::FastForwarded)
+}
+
+pub fn run(pr_number: u32) -> Result<()> {
+    println!(
+        "{}",
+        format!("Getting the stack of PR #{}...", pr_number)
+            .green()
+            .bold()
+    );
+    println!();
+
+    let start = github::get_pr_by_number(pr_number)?;
+    if start.cross_repository {
+        anyhow::bail!(
+            "PR #{} is opened from a fork. Only stacks whose branches live in this repository can be fetched.",
+            pr_number
+        );
+    }
+
+    // Step 1: Find the rest of the stack
+    println!("{}", "Finding stack...".cyan());
+    let mut prs = walk_down(start)?;
+    walk_up(&mut prs)?;
+
+    let base_branch = prs[0].base_ref.clone();
+    println!("  {} (base)", base_branch.green());
+    for pr in &prs {
+        let marker = if pr.number == pr_number {
+            "  ← requested"
+        } else {
+            ""
+        };
+        println!(
+            "    └─ {} #{} {}{}",
+            pr.head_ref.cyan(),
+            pr.number,
+            pr.title.dimmed(),
+            marker
+        );
+    }
+    println!();
+
+    // Step 2: Fetch all heads and create local tracking branches
+    println!("{}", "Fetching branches...".cyan());
+    let remote = git::upstream_remote();
+    let mut fetch_args = vec!["fetch", remote.as_str(), base_branch.as_str()];
+    fetch_args.extend(prs.iter().map(|pr| pr.head_ref.as_str()));
+    git::run(&fetch_args).context("Failed to fetch stack branches")?;
+
+    for branch in std::iter::once(&base_branch).chain(prs.iter().map(|pr| &pr.head_ref)) {
+        match track_branch(&remote, branch)? {
+            Tracked::Created => println!("  {} {} created", "✓".green(), branch.cyan()),
+            Tracked::UpToDate => println!("  {} {} up to date", "✓".dimmed(), branch),
+            Tracked::FastForwarded => {
+                println!("  {} {} fast-forwarded", "✓".green(), branch.cyan())
+            }
+            Tracked::Diverged => println!(
+                "  {} {} has local commits, left as is",
+                "⚠".yellow(),
+                branch.cyan()
+            ),
+        }
+    }
+    println!();
+
+    // Step 3: Track the stack alongside the saved ones
+    let chain = StackConfig {
+        base_branch,
+        branches: prs
+            .iter()
+            .map(|pr| BranchInfo {
+                name: pr.head_ref.clone(),
+                parent: pr.base_ref.clone(),
+                pr_number: Some(pr.number),
+                pushed_sha: git::rev_parse(&format!("{}/{}", remote, pr.head_ref)),
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let mut config = StackConfig::load().context("Failed to load configuration")?;
+    config.track(&chain);
+    config.save().context("Failed to save configuration")?;
+
+    let requested = prs
+        .iter()
+        .find(|pr| pr.number == pr_number)
+        .map(|pr| pr.head_ref.clone())
+        .unwrap_or_default();
+    match git::run(&["checkout", &requested]) {
+        Ok(_) => println!("{} Checked out {}", "✓".green(), requested.cyan()),
+        Err(e) => println!(
+            "{} Could not check out {}: {}",
+            "⚠".yellow(),
+            requested.cyan(),
+            e
+        ),
+    }
+
+    println!();
+    println!(
+        "{} Stack of {} branches ready. Run `gh flow status` to see it.",
+        "✓".green().bold(),
+        chain.branches.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,222 @@
+use crate::{
+    git,
+    schema::StackExport,
+    stack::{BranchInfo, StackConfig},
+    tools::{self, Imported, Tool},
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::io::Read;
+use std::path::Path;
+
+/// Pick the stack the current branch belongs to: its ancestors down to the
+/// trunk, then the chain of children above it. Returns the base branch and
+/// `(branch, parent)` pairs, bottom first.
+fn select_stack(imported: &Imported, current: &str) -> Result<(String, Vec<(String, String)>)> {
+    let parent_of = |branch: &str| {
+        imported
+            .parents
+            .iter()
+            .find(|(b, _)| b == branch)
+            .map(|(_, parent)| parent.clone())
+    };
+    let children_of = |branch: &str| -> Vec<String> {
+        imported
+            .parents
+            .iter()
+            .filter(|(_, parent)| parent == branch)
+            .map(|(b, _)| b.clone())
+            .collect()
+    };
+
+    let start = if parent_of(current).is_some() {
+        current.to_string()
+    } else {
+        let roots = children_of(&imported.trunk);
+        match roots.as_slice() {
+            [root] => root.clone(),
+            [] => anyhow::bail!("No stack found on top of {}", imported.trunk),
+            _ => anyhow::bail!(
+                "Several stacks found ({}). Check out a branch of the one to import.",
+                roots.join(", ")
+            ),
+        }
+    };
+
+    // Walk down to the first branch the tool doesn't track, usually the trunk
+    let mut stack = vec![start.clone()];
+    let mut base = parent_of(&start).unwrap_or_else(|| imported.trunk.clone());
+    while let Some(parent) = parent_of(&base) {
+        if stack.contains(&base) {
+            anyhow::bail!("Branch parents form a cycle at '{}'", base);
+        }
+        stack.insert(0, base);
+        base = parent;
+    }
+
+    // Walk up through the children of the top branch
+    loop {
+        let top = stack.last().cloned().unwrap_or_default();
+        let children: Vec<String> = children_of(&top)
+            .into_iter()
+            .filter(|child| !stack.contains(child))
+            .collect();
+        let Some(next) = children.first() else {
+            break;
+        };
+        if children.len() > 1 {
+            println!(
+                "  {} {} branches are based on {}, following {}",
+                "⚠".yellow(),
+                children.len(),
+                top.cyan(),
+                next.cyan()
+            );
+        }
+        stack.push(next.clone());
+    }
+
+    let mut parent = base.clone();
+    let pairs = stack
+        .into_iter()
+        .map(|branch| {
+            let pair = (branch.clone(), parent.clone());
+            parent = branch;
+            pair
+        })
+        .collect();
+    Ok((base, pairs))
+}
+
+/// Track `chain` alongside the saved stacks and save the result
+fn save_tracked(chain: &StackConfig) -> Result<()> {
+    let mut config = StackConfig::load().context("Failed to load configuration")?;
+    config.track(chain);
+    config.save().context("Failed to save configuration")
+}
+
+fn print_stack(config: &StackConfig) {
+    println!("  {} (base)", config.base_branch.green());
+    for branch in &config.branches {
+        let pr = branch
+            .pr_number
+            .map(|n| format!(" #{}", n))
+            .unwrap_or_default();
+        println!("    └─ {}{}", branch.name.cyan(), pr.dimmed());
+    }
+    println!();
+}
+
+/// Create a branch at its exported commit, fetching the commit if it's missing.
+/// Existing branches are never moved.
+fn restore_branch(name: &str, head: &str) -> Result<()> {
+    match git::rev_parse(name) {
+        Some(sha) if sha == head => return Ok(()),
+        Some(_) => {
+            println!(
+                "  {} {} exists and points elsewhere, left as is",
+                "⚠".yellow(),
+                name.cyan()
+            );
+            return Ok(());
+        }
+        None => {}
+    }
+
+    if git::rev_parse(head).is_none() {
+        let _ = git::run(&["fetch", &git::upstream_remote(), head]);
+    }
+    if git::rev_parse(head).is_none() {
+        println!(
+            "  {} {} not created, commit {} isn't available",
+            "⚠".yellow(),
+            name.cyan(),
+            &head[..7.min(head.len())]
+        );
+        return Ok(());
+    }
+
+    git::run(&["branch", name, head])?;
+    println!("  {} Created {}", "✓".green(), name.cyan());
+    Ok(())
+}
+
+/// Import a stack exported with `gh flow export` (`-` reads stdin)
+fn import_file(file: &Path) -> Result<()> {
+    let (content, source) = if file == Path::new("-") {
+        let mut content = String::new();
+        std::io::stdin().read_to_string(&mut content)?;
+        (content, "stdin".to_string())
+    } else {
+        let content = std::fs::read_to_string(file)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        (content, file.display().to_string())
+    };
+
+    println!(
+        "{}",
+        format!("Importing stack from {}...", source).green().bold()
+    );
+    println!();
+
+    let export = StackExport::parse(&content)?;
+    for branch in &export.branches {
+        if let Some(head) = &branch.head {
+            restore_branch(&branch.name, head)?;
+        }
+    }
+
+    let config = export.into_config();
+    print_stack(&config);
+    save_tracked(&config)?;
+
+    println!(
+        "{} Imported {} branches",
+        "✓".green().bold(),
+        config.branches.len()
+    );
+    Ok(())
+}
+
+/// Import a stack from another tool's metadata, or from an exported file
+pub fn run(file: Option<&Path>, from: Option<Tool>) -> Result<()> {
+    let Some(from) = from else {
+        return import_file(file.context("Give a file to import or --from <tool>")?);
+    };
+
+    println!(
+        "{}",
+        format!("Importing stack from {}...", from.name())
+            .green()
+            .bold()
+    );
+    println!();
+
+    let imported = tools::read(from)?;
+    let current = git::current_branch()?;
+    let (base_branch, pairs) = select_stack(&imported, &current)?;
+
+    let config = StackConfig {
+        base_branch,
+        branches: pairs
+            .into_iter()
+            .map(|(name, parent)| BranchInfo {
+                pr_number: imported.pr_numbers.get(&name).copied(),
+                pushed_sha: None,
+                name,
+                parent,
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    print_stack(&config);
+    save_tracked(&config)?;
+
+    println!(
+        "{} Imported {} branches. Run `gh flow sync` to check the PR bases.",
+        "✓".green().bold(),
+        config.branches.len()
+    );
+    Ok(())
+}
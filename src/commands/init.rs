@@ -1,4 +1,10 @@
-use crate::{git, stack::{self, StackConfig}};
+use super::push;
+use crate::{
+    error::FlowError,
+    git,
+    stack::{self, StackConfig},
+    storage::{self, PullOutcome, Storage},
+};
 use anyhow::{Context, Result};
 use colored::Colorize;
 use std::fs;
@@ -26,29 +32,87 @@ fn create_pr_template() -> Result<(bool, String)> {
     Ok((true, template_path.display().to_string()))
 }
 
-pub fn run(base: &str) -> Result<()> {
+/// Remember which remotes to push to and open PRs against. Must run before
+/// anything reads the remotes, since they are resolved once per process.
+fn configure_remotes(remote: Option<&str>, upstream: Option<&str>) -> Result<()> {
+    for (key, value) in [("gh-flow.remote", remote), ("gh-flow.upstream", upstream)] {
+        let Some(value) = value else {
+            continue;
+        };
+        git::run(&["remote", "get-url", value])
+            .with_context(|| format!("Remote '{}' does not exist", value))?;
+        git::run(&["config", key, value])?;
+        println!("{} Set {} to {}", "✓".green(), key, value.cyan());
+    }
+    Ok(())
+}
+
+/// Switch the storage backend. On a clone that has no stack ref yet, a stack
+/// a teammate already shared is fetched and used instead of discovering one.
+fn configure_storage(storage: Option<Storage>) -> Result<Option<StackConfig>> {
+    let Some(storage) = storage else {
+        return Ok(None);
+    };
+    storage.set()?;
+    println!(
+        "{} Set gh-flow.storage to {}",
+        "✓".green(),
+        storage.name().cyan()
+    );
+
+    if storage != Storage::Refs || storage::load()?.is_some() {
+        return Ok(None);
+    }
+    match storage::pull()? {
+        PullOutcome::Missing => Ok(None),
+        _ => storage::load(),
+    }
+}
+
+pub fn run(
+    base: Option<&str>,
+    remote: Option<&str>,
+    upstream: Option<&str>,
+    storage: Option<Storage>,
+) -> Result<()> {
+    let mut saved = StackConfig::load().context("Failed to load configuration")?;
+    let settings = saved.settings()?;
+    let base = base.unwrap_or(settings.str("base-branch"));
+
     println!("{}", "Initializing gh-flow stack...".green().bold());
     println!("Base branch: {}", base.cyan());
     println!();
 
-    // Verify we're in a git repository
-    git::current_branch()
-        .context("Not in a git repository. Please run this command from within a git repo.")?;
-
     println!("{} Verified git repository", "✓".green());
 
+    configure_remotes(remote, upstream)?;
+    let shared = configure_storage(storage)?;
+
     // Check if base branch exists
     if !git::branch_exists(base)? {
-        anyhow::bail!(
-            "Base branch '{}' does not exist. Please create it or specify a different base branch.",
-            base
-        );
+        return Err(FlowError::BranchMissing(base.to_string()).into());
     }
 
     println!("{} Base branch '{}' exists", "✓".green(), base);
 
+    if let Some(config) = shared {
+        println!(
+            "{} Using the stack shared on {} ({} branches):",
+            "✓".green(),
+            git::push_remote(),
+            config.branches.len()
+        );
+        for branch in &config.branches {
+            println!("    {} ← {}", branch.name.cyan(), branch.parent.dimmed());
+        }
+        println!();
+        println!("{}", "✓ Stack initialized successfully!".green().bold());
+        return Ok(());
+    }
+
     // Auto-discover branch chain from git history
-    let config = StackConfig::discover(base)?;
+    let mut config = StackConfig::discover(base)?;
+    config.keep_saved_state(&saved);
 
     if config.branches.is_empty() {
         println!("{} No branches found in stack", "✓".green());
@@ -63,11 +127,36 @@ pub fn run(base: &str) -> Result<()> {
         }
     }
 
-    // Save configuration
-    config.save().context("Failed to save configuration")?;
+    let unprefixed: Vec<&str> = config
+        .branches
+        .iter()
+        .map(|b| b.name.as_str())
+        .filter(|name| !settings.follows_branch_prefix(name))
+        .collect();
+    if !unprefixed.is_empty() {
+        println!(
+            "{} Branches not starting with '{}': {}",
+            "⚠".yellow(),
+            settings.str("branch-prefix"),
+            unprefixed.join(", ")
+        );
+    }
+
+    // Save the chain alongside any other tracked stacks
+    saved.track(&config);
+    saved.save().context("Failed to save configuration")?;
 
-    let repo_dir = stack::get_repo_config_dir()?;
-    println!("{} Configuration saved to {}", "✓".green(), repo_dir.join("gh-flow.json").display());
+    if Storage::current() == Storage::Refs {
+        println!("{} Stack saved under {}", "✓".green(), storage::STACKS_REF);
+        push::share_stack();
+    } else {
+        let repo_dir = stack::get_repo_config_dir()?;
+        println!(
+            "{} Configuration saved to {}",
+            "✓".green(),
+            repo_dir.join("gh-flow.json").display()
+        );
+    }
 
     // Create PR template if not exists
     match create_pr_template() {
@@ -1,13 +1,26 @@
 use anyhow::{bail, Result};
+use clap::ValueEnum;
 use colored::*;
-use std::process::Command;
 
+use super::ready;
 use crate::github;
 use crate::progress::create_spinner;
 use crate::stack::StackConfig;
 
-pub fn run(auto: bool, wait_ci: bool) -> Result<()> {
-    let config = StackConfig::load()?;
+/// How PRs are merged, overriding the `merge-method` setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeMethod {
+    Squash,
+    Merge,
+    Rebase,
+}
+
+pub fn run(auto: bool, wait_ci: bool, method: Option<MergeMethod>) -> Result<()> {
+    let config = StackConfig::load()?.current_stack()?;
+    let method = match method.and_then(|method| method.to_possible_value()) {
+        Some(value) => value.get_name().to_string(),
+        None => config.settings()?.str("merge-method").to_string(),
+    };
 
     if config.branches.is_empty() {
         println!("{}", "No branches in stack".yellow());
@@ -18,7 +31,9 @@ pub fn run(auto: bool, wait_ci: bool) -> Result<()> {
     println!();
 
     // Get all PRs in order
-    let branches_with_prs: Vec<_> = config.branches.iter()
+    let branches_with_prs: Vec<_> = config
+        .branches
+        .iter()
         .filter(|b| b.pr_number.is_some())
         .collect();
 
@@ -55,7 +70,11 @@ pub fn run(auto: bool, wait_ci: bool) -> Result<()> {
             match ci_status.as_str() {
                 "SUCCESS" => println!("  {} CI passed for PR #{}", "✓".green(), pr_number),
                 "PENDING" => {
-                    println!("  {} CI pending for PR #{}, skipping", "⏳".yellow(), pr_number);
+                    println!(
+                        "  {} CI pending for PR #{}, skipping",
+                        "⏳".yellow(),
+                        pr_number
+                    );
                     continue;
                 }
                 "FAILURE" => {
@@ -72,40 +91,52 @@ pub fn run(auto: bool, wait_ci: bool) -> Result<()> {
         spinner.finish_and_clear();
 
         if review_status != "APPROVED" && !auto {
-            println!("  {} PR #{} not approved, skipping", "⚠".yellow(), pr_number);
+            println!(
+                "  {} PR #{} not approved, skipping",
+                "⚠".yellow(),
+                pr_number
+            );
             continue;
         }
 
         if auto {
             // Enable auto-merge
             let spinner = create_spinner(&format!("Enabling auto-merge for PR #{}", pr_number));
-            let output = Command::new("gh")
-                .args(["pr", "merge", &pr_number.to_string(), "--auto", "--squash"])
-                .output()?;
+            let result = github::merge_pr(pr_number, true, &method);
             spinner.finish_and_clear();
 
-            if output.status.success() {
-                println!("  {} Auto-merge enabled for PR #{}", "✓".green(), pr_number);
-            } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                println!("  {} Failed to enable auto-merge for PR #{}: {}", "✗".red(), pr_number, stderr.trim());
+            match result {
+                Ok(_) => println!("  {} Auto-merge enabled for PR #{}", "✓".green(), pr_number),
+                Err(e) => println!(
+                    "  {} Failed to enable auto-merge for PR #{}: {}",
+                    "✗".red(),
+                    pr_number,
+                    e.to_string().trim()
+                ),
             }
         } else {
             // Merge immediately
             let spinner = create_spinner(&format!("Merging PR #{}", pr_number));
-            let output = Command::new("gh")
-                .args(["pr", "merge", &pr_number.to_string(), "--squash"])
-                .output()?;
+            let result = github::merge_pr(pr_number, false, &method);
             spinner.finish_and_clear();
 
-            if output.status.success() {
-                println!("  {} Merged PR #{}", "✓".green(), pr_number);
-            } else {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                println!("  {} Failed to merge PR #{}: {}", "✗".red(), pr_number, stderr.trim());
-                // Stop on first failure to maintain order
-                if !auto {
-                    break;
+            match result {
+                Ok(_) => {
+                    println!("  {} Merged PR #{}", "✓".green(), pr_number);
+                    // The next PR becomes the bottom of the stack
+                    ready::apply_draft_policy(&config)?;
+                }
+                Err(e) => {
+                    println!(
+                        "  {} Failed to merge PR #{}: {}",
+                        "✗".red(),
+                        pr_number,
+                        e.to_string().trim()
+                    );
+                    // Stop on first failure to maintain order
+                    if !auto {
+                        break;
+                    }
                 }
             }
         }
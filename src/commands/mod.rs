@@ -1,9 +1,18 @@
 pub mod adopt;
 pub mod completions;
+pub mod config;
+pub mod doctor;
+pub mod export;
+pub mod get;
+pub mod import;
 pub mod init;
 pub mod merge;
 pub mod pr;
+pub mod prune;
 pub mod push;
+pub mod ready;
 pub mod split;
 pub mod status;
+pub mod submit;
 pub mod sync;
+pub mod template;
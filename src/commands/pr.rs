@@ -1,55 +1,593 @@
-use crate::{git, github, stack::{self, StackConfig}};
+use crate::codeowners::{CodeOwners, Suggestion};
+use crate::{
+    git, github,
+    stack::{self, BranchInfo, DraftPolicy, StackConfig, TitleStrategy},
+    template,
+};
 use anyhow::{Context, Result};
+use clap::{Args, ValueEnum};
 use colored::Colorize;
+use std::collections::HashMap;
 
-fn generate_stack_visualization(config: &StackConfig, current_branch: &str) -> String {
-    let mut stack_viz = String::from("```\n");
-    stack_viz.push_str(&format!("{} (base)\n", config.base_branch));
+/// Markers delimiting the gh-flow managed section of a PR body
+const STACK_SECTION_START: &str = "<!-- gh-flow:stack:start -->";
+const STACK_SECTION_END: &str = "<!-- gh-flow:stack:end -->";
 
-    for (idx, branch) in config.branches.iter().enumerate() {
-        let marker = if branch.name == current_branch {
-            "▶"
-        } else {
-            " "
+/// Where to insert the stack section when a PR body doesn't have one yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SectionPlacement {
+    Top,
+    Bottom,
+}
+
+pub const DEFAULT_PR_TEMPLATE: &str = "{{#if commit_messages}}## Commits\n\n{{commit_messages}}\n\n{{/if}}## Stack\n\n{{stack}}\n\n*This PR is part of a stack. Use [gh-flow](https://github.com/say828/gh-flow) to manage stacked PRs.*\n";
+
+/// Title settings given on the command line, overriding the saved stack settings
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TitleOptions {
+    pub strategy: Option<TitleStrategy>,
+    pub position_prefix: Option<bool>,
+}
+
+impl TitleOptions {
+    /// Store the overrides in the user's settings. Returns true if anything changed.
+    fn apply(&self, config: &mut StackConfig) -> Result<bool> {
+        let before = config.settings.clone();
+        if let Some(strategy) = self.strategy {
+            config.set_setting("pr.title-strategy", Some(serde_json::to_value(strategy)?))?;
+        }
+        if let Some(prefix) = self.position_prefix {
+            config.set_setting("pr.title-position-prefix", Some(prefix.into()))?;
+        }
+        Ok(before != config.settings)
+    }
+}
+
+/// Reviewers, labels, assignees and milestone given on the command line,
+/// applied on top of the stack settings
+#[derive(Debug, Default, Clone, Args)]
+pub struct MetadataOptions {
+    /// Request a review from a user (repeatable or comma-separated)
+    #[arg(long = "reviewer", value_delimiter = ',')]
+    pub reviewers: Vec<String>,
+
+    /// Request a review from a team, as `team` or `org/team`
+    #[arg(long = "team-reviewer", value_delimiter = ',')]
+    pub team_reviewers: Vec<String>,
+
+    /// Add a label (repeatable or comma-separated)
+    #[arg(long = "label", value_delimiter = ',')]
+    pub labels: Vec<String>,
+
+    /// Assign a user, `@me` for yourself (repeatable or comma-separated)
+    #[arg(long = "assignee", value_delimiter = ',')]
+    pub assignees: Vec<String>,
+
+    /// Set the milestone
+    #[arg(long)]
+    pub milestone: Option<String>,
+
+    /// Request reviews from the CODEOWNERS of each branch's changed files
+    #[arg(long)]
+    pub codeowners: bool,
+}
+
+fn push_unique(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|v| v.eq_ignore_ascii_case(value)) {
+        list.push(value.to_string());
+    }
+}
+
+/// Resolves the metadata to apply to each PR of the stack
+pub struct MetadataPlan {
+    /// Stack settings merged with the command line options
+    common: github::PrMetadata,
+    codeowners: Option<CodeOwners>,
+    request_codeowners: bool,
+    /// Login of the authenticated user, empty when unknown
+    me: String,
+}
+
+impl MetadataPlan {
+    pub fn new(config: &StackConfig, options: &MetadataOptions) -> Self {
+        let settings = &config.pr;
+        let mut common = github::PrMetadata::default();
+
+        for reviewer in settings.reviewers.iter().chain(&options.reviewers) {
+            push_unique(&mut common.reviewers, reviewer.trim_start_matches('@'));
+        }
+
+        // gh expects teams as org/team
+        let owner = stack::get_repo().map(|repo| repo.owner).unwrap_or_default();
+        for team in settings
+            .team_reviewers
+            .iter()
+            .chain(&options.team_reviewers)
+        {
+            let team = team.trim_start_matches('@');
+            if team.contains('/') || owner.is_empty() {
+                push_unique(&mut common.reviewers, team);
+            } else {
+                push_unique(&mut common.reviewers, &format!("{}/{}", owner, team));
+            }
+        }
+
+        for label in settings.labels.iter().chain(&options.labels) {
+            push_unique(&mut common.labels, label);
+        }
+        for assignee in settings.assignees.iter().chain(&options.assignees) {
+            push_unique(&mut common.assignees, assignee);
+        }
+        common.milestone = options
+            .milestone
+            .clone()
+            .or_else(|| settings.milestone.clone());
+
+        let codeowners = CodeOwners::load().ok().flatten();
+        let me =
+            if codeowners.is_some() || !common.assignees.is_empty() || !common.reviewers.is_empty()
+            {
+                github::current_user().unwrap_or_default()
+            } else {
+                String::new()
+            };
+
+        // You can't request a review from yourself
+        common.reviewers.retain(|r| !r.eq_ignore_ascii_case(&me));
+
+        Self {
+            common,
+            codeowners,
+            request_codeowners: options.codeowners || settings.codeowners,
+            me,
+        }
+    }
+
+    /// Metadata for one branch, plus the CODEOWNERS suggestion for its changes
+    pub fn for_branch(&self, branch: &BranchInfo) -> (github::PrMetadata, Suggestion) {
+        let mut metadata = self.common.clone();
+
+        let suggestion = match &self.codeowners {
+            Some(codeowners) => {
+                let files = git::changed_files(&branch.parent, &branch.name).unwrap_or_default();
+                codeowners.suggest(files.iter().map(String::as_str), &self.me)
+            }
+            None => Suggestion::default(),
         };
-        let pr_info = if let Some(pr_num) = branch.pr_number {
-            format!(" PR #{}", pr_num)
-        } else {
-            String::new()
+
+        if self.request_codeowners {
+            for owner in suggestion.users.iter().chain(&suggestion.teams) {
+                push_unique(&mut metadata.reviewers, owner);
+            }
+        }
+
+        (metadata, suggestion)
+    }
+
+    /// The part of `desired` that the PR doesn't have yet
+    fn missing(
+        &self,
+        desired: &github::PrMetadata,
+        current: &github::PrMetadata,
+    ) -> github::PrMetadata {
+        let has = |list: &[String], value: &str| {
+            let value = if value == "@me" {
+                self.me.as_str()
+            } else {
+                value
+            };
+            // Teams may be reported by slug only
+            let short = value.rsplit('/').next().unwrap_or(value);
+            list.iter()
+                .any(|v| v.eq_ignore_ascii_case(value) || v.eq_ignore_ascii_case(short))
         };
+        let missing = |wanted: &[String], have: &[String]| {
+            wanted.iter().filter(|v| !has(have, v)).cloned().collect()
+        };
+
+        github::PrMetadata {
+            reviewers: missing(&desired.reviewers, &current.reviewers),
+            labels: missing(&desired.labels, &current.labels),
+            assignees: missing(&desired.assignees, &current.assignees),
+            milestone: desired
+                .milestone
+                .clone()
+                .filter(|m| current.milestone.as_deref() != Some(m.as_str())),
+        }
+    }
+}
+
+fn print_suggestion(suggestion: &Suggestion) {
+    if suggestion.is_empty() {
+        return;
+    }
+    let owners: Vec<String> = suggestion
+        .users
+        .iter()
+        .chain(&suggestion.teams)
+        .map(|o| format!("@{}", o))
+        .collect();
+    println!(
+        "    {} {}",
+        "Suggested reviewers (CODEOWNERS):".dimmed(),
+        owners.join(", ")
+    );
+}
+
+/// Turn `feat/add-user-table` into `Add user table`
+fn humanize_branch_name(branch: &str) -> String {
+    let name = branch.rsplit('/').next().unwrap_or(branch);
+    let words = name.replace(['-', '_'], " ");
+    let words = words.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut chars = words.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => branch.to_string(),
+    }
+}
 
-        stack_viz.push_str(&format!("{}└─ {}{}\n", marker, branch.name, pr_info));
+/// Build the PR title for a branch according to the stack's title settings.
+/// The position prefix counts the chain the branch is on.
+pub fn generate_title(config: &StackConfig, branch: &str) -> String {
+    let last_commit = || {
+        git::run(&["log", "-1", "--pretty=%s", branch])
+            .unwrap_or_else(|_| format!("Changes in {}", branch))
+    };
 
-        if idx < config.branches.len() - 1 {
-            stack_viz.push_str("  ↓\n");
+    let title = match config.pr.title_strategy {
+        TitleStrategy::LastCommit => last_commit(),
+        TitleStrategy::FirstCommit => {
+            let parent = config
+                .get_branch(branch)
+                .map(|b| b.parent.as_str())
+                .unwrap_or(&config.base_branch);
+            git::commit_subjects(&format!("{}..{}", parent, branch))
+                .ok()
+                .and_then(|subjects| subjects.into_iter().next())
+                .unwrap_or_else(last_commit)
         }
+        TitleStrategy::BranchName => humanize_branch_name(branch),
+    };
+
+    if !config.pr.title_position_prefix {
+        return title;
     }
-    stack_viz.push_str("```");
 
-    // Load template from file or use default
-    if let Some(tmpl) = stack::load_pr_template() {
-        tmpl.replace("{{stack}}", &stack_viz)
-            .replace("{{branch}}", current_branch)
+    let chain = config.chain_through(branch);
+    match chain.iter().position(|b| b.name == branch) {
+        Some(idx) => format!("[{}/{}] {}", idx + 1, chain.len(), title),
+        None => title,
+    }
+}
+
+/// Live PR state keyed by PR number
+pub type PrStatuses = HashMap<u32, github::PrDetails>;
+
+/// Fetch the live state of every PR in the stack. PRs that fail to load are left out.
+pub fn fetch_pr_statuses(config: &StackConfig) -> PrStatuses {
+    config
+        .branches
+        .iter()
+        .filter_map(|b| b.pr_number)
+        .filter_map(|n| github::get_pr_details(n).ok().map(|d| (n, d)))
+        .collect()
+}
+
+fn status_label(details: &github::PrDetails) -> String {
+    let mut labels = Vec::new();
+
+    match details.state.as_str() {
+        "MERGED" => return "🟣 merged".to_string(),
+        "CLOSED" => return "⚫ closed".to_string(),
+        _ => {}
+    }
+
+    if details.is_draft {
+        labels.push("📝 draft");
+    }
+    match details.review_decision.as_deref() {
+        Some("APPROVED") => labels.push("✅ approved"),
+        Some("CHANGES_REQUESTED") => labels.push("🔴 changes requested"),
+        _ => {}
+    }
+    if details.ci_failing() {
+        labels.push("❌ CI failing");
+    }
+
+    if labels.is_empty() {
+        "🟢 open".to_string()
     } else {
-        // Default template
-        format!(
-            "## Stack\n\n{}\n\n*This PR is part of a stack. Use [gh-flow](https://github.com/say828/gh-flow) to manage stacked PRs.*\n",
-            stack_viz
-        )
+        labels.join(" · ")
     }
 }
 
-pub fn create(draft: bool) -> Result<()> {
+fn generate_stack_visualization(
+    config: &StackConfig,
+    current_branch: &str,
+    statuses: &PrStatuses,
+) -> String {
+    let mut stack_viz = format!("Based on `{}`\n\n", config.base_branch);
+    stack_viz.push_str("| | PR | Branch | Status |\n");
+    stack_viz.push_str("|:-:|---|---|---|\n");
+
+    for branch in &config.chain_through(current_branch) {
+        let is_current = branch.name == current_branch;
+        let marker = if is_current { "👉" } else { "" };
+
+        // GitHub turns #123 into a link to the PR
+        let pr = branch
+            .pr_number
+            .map(|n| format!("#{}", n))
+            .unwrap_or_else(|| "—".to_string());
+        let name = format!("`{}`", branch.name);
+        let status = match branch.pr_number {
+            Some(n) => statuses.get(&n).map(status_label).unwrap_or_default(),
+            None => "not created".to_string(),
+        };
+
+        if is_current {
+            stack_viz.push_str(&format!(
+                "| {} | **{}** | **{}** | {} |\n",
+                marker, pr, name, status
+            ));
+        } else {
+            stack_viz.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                marker, pr, name, status
+            ));
+        }
+    }
+
+    stack_viz.trim_end().to_string()
+}
+
+/// Build the template variables for one branch of the stack, over the chain
+/// the branch is on
+pub fn template_context(
+    config: &StackConfig,
+    current_branch: &str,
+    title: &str,
+    statuses: &PrStatuses,
+) -> template::Context {
+    let mut ctx = template::Context::new();
+    let chain = config.chain_through(current_branch);
+    let total = chain.len();
+    let position = chain.iter().position(|b| b.name == current_branch);
+    let info = position.map(|idx| &chain[idx]);
+    let parent = info
+        .map(|b| b.parent.clone())
+        .unwrap_or_else(|| config.base_branch.clone());
+
+    let messages =
+        git::commit_messages(&format!("{}..{}", parent, current_branch)).unwrap_or_default();
+    let commits = messages
+        .iter()
+        .map(|commit| format!("- {}", commit.subject))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let commit_messages = messages
+        .iter()
+        .map(|commit| {
+            if commit.body.is_empty() {
+                format!("### {}", commit.subject)
+            } else {
+                format!("### {}\n\n{}", commit.subject, commit.body)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    ctx.set(
+        "stack",
+        generate_stack_visualization(config, current_branch, statuses),
+    );
+    ctx.set("branch", current_branch);
+    ctx.set("title", title);
+    ctx.set("parent", parent);
+    ctx.set("base", config.base_branch.as_str());
+    ctx.set(
+        "pr_number",
+        info.and_then(|b| b.pr_number)
+            .map(|n| n.to_string())
+            .unwrap_or_default(),
+    );
+    ctx.set(
+        "position",
+        position.map(|i| (i + 1).to_string()).unwrap_or_default(),
+    );
+    ctx.set("total", total.to_string());
+    ctx.set("commits", commits);
+    ctx.set("commit_messages", commit_messages);
+    ctx.set("first", position == Some(0));
+    ctx.set("last", total > 0 && position == Some(total - 1));
+
+    let entries = chain
+        .iter()
+        .enumerate()
+        .map(|(idx, b)| {
+            let mut entry = template::Context::new();
+            entry.set("name", b.name.as_str());
+            entry.set("parent", b.parent.as_str());
+            entry.set(
+                "pr_number",
+                b.pr_number.map(|n| n.to_string()).unwrap_or_default(),
+            );
+            entry.set("position", (idx + 1).to_string());
+            entry.set("current", b.name == current_branch);
+            entry
+        })
+        .collect();
+    ctx.set_list("branches", entries);
+
+    ctx
+}
+
+/// Render the PR body for a branch from the configured template (or the default one)
+pub fn render_pr_body(
+    config: &StackConfig,
+    current_branch: &str,
+    title: &str,
+    statuses: &PrStatuses,
+) -> Result<template::Rendered> {
+    let tmpl = stack::load_pr_template().unwrap_or_else(|| DEFAULT_PR_TEMPLATE.to_string());
+    let ctx = template_context(config, current_branch, title, statuses);
+    template::render(&tmpl, &ctx)
+        .context("Invalid PR template. Run `gh flow template check` for details.")
+}
+
+/// Rendered PR body wrapped in the gh-flow section markers
+pub fn generate_stack_section(
+    config: &StackConfig,
+    current_branch: &str,
+    title: &str,
+    statuses: &PrStatuses,
+) -> Result<String> {
+    let rendered = render_pr_body(config, current_branch, title, statuses)?;
+    Ok(format!(
+        "{}\n{}\n{}",
+        STACK_SECTION_START,
+        rendered.output.trim(),
+        STACK_SECTION_END
+    ))
+}
+
+/// Replace the gh-flow section in an existing PR body, keeping everything
+/// the author wrote around it. Inserts the section when it is missing.
+pub fn splice_stack_section(body: &str, section: &str, placement: SectionPlacement) -> String {
+    if let Some(start) = body.find(STACK_SECTION_START) {
+        if let Some(end_offset) = body[start..].find(STACK_SECTION_END) {
+            let end = start + end_offset + STACK_SECTION_END.len();
+            return format!("{}{}{}", &body[..start], section, &body[end..]);
+        }
+    }
+
+    // A marker without its partner was left behind by an edit. Drop it, or the
+    // next update would take everything up to the new section for ours.
+    let body = body
+        .replace(STACK_SECTION_START, "")
+        .replace(STACK_SECTION_END, "");
+    let body = body.trim();
+    if body.is_empty() {
+        return section.to_string();
+    }
+
+    match placement {
+        SectionPlacement::Top => format!("{}\n\n{}", section, body),
+        SectionPlacement::Bottom => format!("{}\n\n{}", body, section),
+    }
+}
+
+/// Create or refresh the single gh-flow comment on a PR.
+/// Returns false when the existing comment was already up to date.
+fn upsert_stack_comment(pr_number: u32, section: &str, dry_run: bool) -> Result<bool> {
+    let existing = github::find_pr_comment(pr_number, STACK_SECTION_START)?;
+    if let Some(comment_id) = existing {
+        if github::get_pr_comment_body(comment_id)?.trim() == section.trim() {
+            return Ok(false);
+        }
+    }
+    if !dry_run {
+        match existing {
+            Some(comment_id) => github::update_pr_comment(comment_id, section)?,
+            None => github::create_pr_comment(pr_number, section)?,
+        }
+    }
+    Ok(true)
+}
+
+/// Remove the gh-flow section from a PR body, keeping the author's text
+pub fn remove_stack_section(body: &str) -> String {
+    let mut body = body.to_string();
+    if let Some(start) = body.find(STACK_SECTION_START) {
+        if let Some(end_offset) = body[start..].find(STACK_SECTION_END) {
+            let end = start + end_offset + STACK_SECTION_END.len();
+            body = format!(
+                "{}\n\n{}",
+                body[..start].trim_end(),
+                body[end..].trim_start()
+            );
+        }
+    }
+    body.replace(STACK_SECTION_START, "")
+        .replace(STACK_SECTION_END, "")
+        .trim()
+        .to_string()
+}
+
+/// Put the stack section where the comment mode wants it, in the PR body or
+/// in the sticky comment, and remove it from the other place.
+/// Returns false when the PR was already up to date; with `dry_run`, nothing
+/// is written and the result tells whether it would be.
+pub fn write_stack_section(
+    pr_number: u32,
+    body: &str,
+    section: &str,
+    placement: SectionPlacement,
+    comment: bool,
+    dry_run: bool,
+) -> Result<bool> {
+    if comment {
+        let mut changed = upsert_stack_comment(pr_number, section, dry_run)?;
+        if body.contains(STACK_SECTION_START) || body.contains(STACK_SECTION_END) {
+            if !dry_run {
+                github::update_pr_body(pr_number, &remove_stack_section(body))?;
+            }
+            changed = true;
+        }
+        return Ok(changed);
+    }
+
+    // Keep the author's description, only replace the stack section
+    let new_body = splice_stack_section(body, section, placement);
+    let mut changed = false;
+    if new_body.trim() != body.trim() {
+        if !dry_run {
+            github::update_pr_body(pr_number, &new_body)?;
+        }
+        changed = true;
+    }
+    if let Some(comment_id) = github::find_pr_comment(pr_number, STACK_SECTION_START)? {
+        if !dry_run {
+            github::delete_pr_comment(comment_id)?;
+        }
+        changed = true;
+    }
+    Ok(changed)
+}
+
+/// Save `--comment` as the user's `pr.stack-comment` setting and return the
+/// effective comment mode
+fn comment_mode(config: &mut StackConfig, comment: Option<bool>) -> Result<bool> {
+    if let Some(comment) = comment {
+        config.set_setting("pr.stack-comment", Some(comment.into()))?;
+    }
+    Ok(config.pr.stack_comment)
+}
+
+pub fn create(
+    draft: bool,
+    comment: Option<bool>,
+    title_options: TitleOptions,
+    metadata_options: MetadataOptions,
+) -> Result<()> {
     println!("{}", "Creating PRs for stack...".green().bold());
 
+    // Load configuration; only the stack HEAD is on is worked on
+    let mut saved = StackConfig::load().context("Failed to load configuration")?;
+    let mut config = saved.current_stack()?;
+
+    let bottom_ready = config.pr.draft_policy == DraftPolicy::BottomReady;
     if draft {
         println!("{}", "PRs will be created as drafts".dimmed());
+    } else if bottom_ready {
+        println!(
+            "{}",
+            "PRs above the bottom-most open PR will be created as drafts".dimmed()
+        );
     }
     println!();
 
-    // Load configuration
-    let mut config = StackConfig::load().context("Failed to load configuration")?;
-
     if config.branches.is_empty() {
         println!(
             "{}",
@@ -58,8 +596,15 @@ pub fn create(draft: bool) -> Result<()> {
         return Ok(());
     }
 
+    title_options.apply(&mut config)?;
+    let comment = comment_mode(&mut config, comment)?;
+    let metadata_plan = MetadataPlan::new(&config, &metadata_options);
+
     let mut created_count = 0;
     let mut skipped_count = 0;
+    let statuses = fetch_pr_statuses(&config);
+    // Whether a PR below the current branch is still open (for the bottom-ready policy)
+    let mut open_below = false;
 
     for i in 0..config.branches.len() {
         let branch_name = config.branches[i].name.clone();
@@ -82,21 +627,43 @@ pub fn create(draft: bool) -> Result<()> {
                 format!("(PR #{})", existing_pr.number).dimmed()
             );
             config.branches[i].pr_number = Some(existing_pr.number);
+            open_below |= existing_pr.state == "OPEN";
             skipped_count += 1;
             continue;
         }
 
-        // Get commit message for PR title
-        let title = git::run(&["log", "-1", "--pretty=%s", &branch_name])
-            .unwrap_or_else(|_| format!("Changes in {}", branch_name));
+        let title = generate_title(&config, &branch_name);
+        let (metadata, suggestion) = metadata_plan.for_branch(&config.branches[i]);
 
-        // Generate PR body with stack visualization
-        let body = generate_stack_visualization(&config, &branch_name);
+        // Generate PR body from the template (posted as a comment later in comment mode)
+        let body = if comment {
+            String::new()
+        } else {
+            match generate_stack_section(&config, &branch_name, &title, &statuses) {
+                Ok(body) => body,
+                Err(e) => {
+                    println!("{} {:#}", "✗".red(), e);
+                    continue;
+                }
+            }
+        };
 
         // Create PR
-        match github::create_pr(&branch_name, &parent_name, &title, &body, draft) {
+        let as_draft = draft || (bottom_ready && open_below);
+        match github::create_pr(
+            &branch_name,
+            &parent_name,
+            &title,
+            &body,
+            as_draft,
+            &metadata,
+        ) {
             Ok(pr_number) => {
+                open_below = true;
                 println!("{} {}", "✓".green(), format!("PR #{}", pr_number).green());
+                if !metadata_plan.request_codeowners {
+                    print_suggestion(&suggestion);
+                }
                 config.branches[i].pr_number = Some(pr_number);
                 created_count += 1;
             }
@@ -107,7 +674,43 @@ pub fn create(draft: bool) -> Result<()> {
     }
 
     // Save updated configuration
-    config.save().context("Failed to save configuration")?;
+    saved
+        .save_stack(&config)
+        .context("Failed to save configuration")?;
+
+    // Every PR number and status is known now: refresh the stack table of the
+    // whole stack, and post it as a comment in comment mode
+    if created_count > 0 {
+        println!();
+        let statuses = fetch_pr_statuses(&config);
+        for branch_info in &config.branches {
+            let Some(pr_number) = branch_info.pr_number else {
+                continue;
+            };
+            print!(
+                "Updating stack of PR #{} ({}) ... ",
+                pr_number,
+                branch_info.name.cyan()
+            );
+            let result = github::get_pr_content(pr_number).and_then(|content| {
+                let section =
+                    generate_stack_section(&config, &branch_info.name, &content.title, &statuses)?;
+                write_stack_section(
+                    pr_number,
+                    &content.body,
+                    &section,
+                    SectionPlacement::Bottom,
+                    comment,
+                    false,
+                )
+            });
+            match result {
+                Ok(true) => println!("{}", "✓".green()),
+                Ok(false) => println!("{}", "unchanged".dimmed()),
+                Err(e) => println!("{} {:#}", "✗".red(), e),
+            }
+        }
+    }
 
     println!();
     println!(
@@ -120,19 +723,37 @@ pub fn create(draft: bool) -> Result<()> {
     Ok(())
 }
 
-pub fn update() -> Result<()> {
+pub fn update(
+    placement: SectionPlacement,
+    comment: Option<bool>,
+    titles: bool,
+    title_options: TitleOptions,
+    metadata_options: MetadataOptions,
+) -> Result<()> {
     println!("{}", "Updating PRs...".green().bold());
     println!();
 
-    // Load configuration
-    let config = StackConfig::load().context("Failed to load configuration")?;
+    // Load configuration; only the stack HEAD is on is worked on
+    let mut saved = StackConfig::load().context("Failed to load configuration")?;
+    let mut config = saved.current_stack()?;
 
     if config.branches.is_empty() {
         println!("{}", "No branches in stack.".yellow());
         return Ok(());
     }
 
+    title_options.apply(&mut config)?;
+    let comment = comment_mode(&mut config, comment)?;
+    if config.settings != saved.settings {
+        saved
+            .save_stack(&config)
+            .context("Failed to save configuration")?;
+    }
+
     let mut updated_count = 0;
+    let mut unchanged_count = 0;
+    let statuses = fetch_pr_statuses(&config);
+    let metadata_plan = MetadataPlan::new(&config, &metadata_options);
 
     for branch_info in &config.branches {
         if let Some(pr_number) = branch_info.pr_number {
@@ -142,23 +763,214 @@ pub fn update() -> Result<()> {
                 branch_info.name.cyan()
             );
 
-            // Generate updated stack visualization
-            let body = generate_stack_visualization(&config, &branch_info.name);
+            let content = match github::get_pr_content(pr_number) {
+                Ok(content) => content,
+                Err(e) => {
+                    println!("{} {}", "✗".red(), e);
+                    continue;
+                }
+            };
+            // Add whatever reviewers, labels, etc. the PR is still missing
+            let (metadata, suggestion) = metadata_plan.for_branch(branch_info);
+            if !metadata.is_empty() {
+                let missing = github::get_pr_metadata(pr_number)
+                    .map(|current| metadata_plan.missing(&metadata, &current));
+                match missing {
+                    Ok(missing) if !missing.is_empty() => {
+                        if let Err(e) = github::add_pr_metadata(pr_number, &missing) {
+                            println!("{} {}", "✗".red(), e);
+                            continue;
+                        }
+                        print!("{} ... ", "metadata".dimmed());
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        println!("{} {}", "✗".red(), e);
+                        continue;
+                    }
+                }
+            }
+
+            // Re-sync the title when positions or the strategy changed
+            let mut title = content.title.clone();
+            if titles {
+                let new_title = generate_title(&config, &branch_info.name);
+                if new_title != content.title {
+                    if let Err(e) = github::update_pr_title(pr_number, &new_title) {
+                        println!("{} {}", "✗".red(), e);
+                        continue;
+                    }
+                    print!("title → {} ... ", new_title.dimmed());
+                    title = new_title;
+                }
+            }
+
+            let section =
+                match generate_stack_section(&config, &branch_info.name, &title, &statuses) {
+                    Ok(section) => section,
+                    Err(e) => {
+                        println!("{} {:#}", "✗".red(), e);
+                        continue;
+                    }
+                };
 
-            match github::update_pr_body(pr_number, &body) {
-                Ok(_) => {
+            match write_stack_section(
+                pr_number,
+                &content.body,
+                &section,
+                placement,
+                comment,
+                false,
+            ) {
+                Ok(true) => {
                     println!("{}", "✓".green());
                     updated_count += 1;
                 }
+                Ok(false) => {
+                    println!("{}", "unchanged".dimmed());
+                    unchanged_count += 1;
+                }
                 Err(e) => {
                     println!("{} {}", "✗".red(), e);
                 }
             }
+
+            if !metadata_plan.request_codeowners {
+                print_suggestion(&suggestion);
+            }
         }
     }
 
     println!();
-    println!("{} {} PRs updated", "✓".green(), updated_count);
+    println!(
+        "{} {} PRs updated, {} unchanged",
+        "✓".green(),
+        updated_count,
+        unchanged_count
+    );
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(content: &str) -> String {
+        format!(
+            "{}\n{}\n{}",
+            STACK_SECTION_START, content, STACK_SECTION_END
+        )
+    }
+
+    /// Two stacks on main: a1 → a2 → a3 and b1 → b2
+    fn two_stacks() -> StackConfig {
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        for (name, parent) in [
+            ("a1", "main"),
+            ("b1", "main"),
+            ("a2", "a1"),
+            ("b2", "b1"),
+            ("a3", "a2"),
+        ] {
+            config.add_branch(name.to_string(), parent.to_string());
+        }
+        config.pr.title_strategy = TitleStrategy::BranchName;
+        config.pr.title_position_prefix = true;
+        config
+    }
+
+    #[test]
+    fn title_position_counts_the_branch_own_stack() {
+        let config = two_stacks();
+        assert_eq!(generate_title(&config, "b2"), "[2/2] B2");
+        assert_eq!(generate_title(&config, "a3"), "[3/3] A3");
+    }
+
+    #[test]
+    fn stack_table_lists_only_the_branch_own_stack() {
+        let table = generate_stack_visualization(&two_stacks(), "b1", &PrStatuses::new());
+        assert!(table.contains("`b1`") && table.contains("`b2`"));
+        assert!(!table.contains("`a1`"));
+    }
+
+    #[test]
+    fn splice_replaces_existing_section_and_keeps_author_text() {
+        let body = format!("Intro\n\n{}\n\nOutro", section("old"));
+        let spliced = splice_stack_section(&body, &section("new"), SectionPlacement::Bottom);
+        assert_eq!(spliced, format!("Intro\n\n{}\n\nOutro", section("new")));
+    }
+
+    #[test]
+    fn splice_replaces_only_the_first_section() {
+        let body = format!("{}\nmiddle\n{}", section("one"), section("two"));
+        let spliced = splice_stack_section(&body, &section("new"), SectionPlacement::Top);
+        assert_eq!(
+            spliced,
+            format!("{}\nmiddle\n{}", section("new"), section("two"))
+        );
+    }
+
+    #[test]
+    fn splice_inserts_section_at_placement() {
+        let top = splice_stack_section("  Description \n", &section("s"), SectionPlacement::Top);
+        assert_eq!(top, format!("{}\n\nDescription", section("s")));
+        let bottom = splice_stack_section("Description", &section("s"), SectionPlacement::Bottom);
+        assert_eq!(bottom, format!("Description\n\n{}", section("s")));
+    }
+
+    #[test]
+    fn splice_into_empty_body_is_just_the_section() {
+        assert_eq!(
+            splice_stack_section("", &section("s"), SectionPlacement::Top),
+            section("s")
+        );
+        assert_eq!(
+            splice_stack_section(" \n ", &section("s"), SectionPlacement::Bottom),
+            section("s")
+        );
+    }
+
+    #[test]
+    fn splice_drops_orphaned_start_marker() {
+        let body = format!("Intro\n{}\nhalf a section", STACK_SECTION_START);
+        let spliced = splice_stack_section(&body, &section("new"), SectionPlacement::Bottom);
+        assert_eq!(
+            spliced,
+            format!("Intro\n\nhalf a section\n\n{}", section("new"))
+        );
+        assert_eq!(spliced.matches(STACK_SECTION_START).count(), 1);
+    }
+
+    #[test]
+    fn splice_drops_end_marker_before_start() {
+        let body = format!("{}\nIntro", STACK_SECTION_END);
+        let spliced = splice_stack_section(&body, &section("new"), SectionPlacement::Top);
+        assert_eq!(spliced, format!("{}\n\nIntro", section("new")));
+    }
+
+    #[test]
+    fn remove_section_keeps_author_text() {
+        let body = format!("Intro\n\n{}\n\nOutro", section("table"));
+        assert_eq!(remove_stack_section(&body), "Intro\n\nOutro");
+    }
+
+    #[test]
+    fn remove_section_from_section_only_body() {
+        assert_eq!(remove_stack_section(&section("table")), "");
+    }
+
+    #[test]
+    fn remove_section_drops_orphaned_markers() {
+        let body = format!("Intro\n{}\nrest", STACK_SECTION_START);
+        assert_eq!(remove_stack_section(&body), "Intro\n\nrest");
+    }
+
+    #[test]
+    fn remove_section_without_section_is_unchanged() {
+        assert_eq!(remove_stack_section("Just text\n"), "Just text");
+    }
+}
@@ -0,0 +1,366 @@
+use super::push;
+use crate::{
+    git,
+    github::{self, PullRequest},
+    stack::StackConfig,
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use dialoguer::MultiSelect;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Parse an age like `30d`, `2w`, `12h` or `90m`; a bare number is days
+pub fn parse_age(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("'{}' is not an age like 30d, 2w, 12h or 90m", value);
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    let unit: u64 = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" | "" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let secs = number.checked_mul(unit).ok_or_else(invalid)?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// A branch that can be pruned, and why
+struct Candidate {
+    name: String,
+    reason: String,
+    /// Still exists locally
+    local: bool,
+    /// Still exists on the push remote
+    remote: bool,
+    /// Time of the last commit, as Unix time
+    committed: Option<u64>,
+}
+
+impl Candidate {
+    fn describe(&self) -> String {
+        let places = match (self.local, self.remote) {
+            (true, true) => format!("local and {}", git::push_remote()),
+            (true, false) => "local".to_string(),
+            (false, true) => git::push_remote(),
+            (false, false) => "stack only".to_string(),
+        };
+        format!("{}  {} ({})", self.name, self.reason, places)
+    }
+}
+
+/// A branch that would be pruned but is kept, and why
+struct Kept {
+    name: String,
+    why: String,
+}
+
+/// A local branch as `for-each-ref` reports it
+struct LocalBranch {
+    name: String,
+    /// Its upstream branch was deleted on the remote
+    gone: bool,
+    committed: Option<u64>,
+}
+
+fn local_branches() -> Result<Vec<LocalBranch>> {
+    let output = git::run(&[
+        "for-each-ref",
+        "--format=%(refname:short)%1f%(upstream:track)%1f%(committerdate:unix)",
+        "refs/heads/",
+    ])?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\x1f');
+            Some(LocalBranch {
+                name: fields.next()?.to_string(),
+                gone: fields.next()? == "[gone]",
+                committed: fields.next()?.parse().ok(),
+            })
+        })
+        .collect())
+}
+
+/// Whether `branch` has commits that were never pushed: it is ahead of its
+/// remote branch or, with that gone, of its last PR's head and every remote branch
+fn unpushed(branch: &str, pr_head: Option<&str>) -> bool {
+    if let Some(remote_tip) = git::remote_tip(branch) {
+        return !git::is_ancestor(branch, &remote_tip);
+    }
+    if pr_head.is_some_and(|head| !head.is_empty() && git::is_ancestor(branch, head)) {
+        return false;
+    }
+    !git::run(&["rev-list", "--max-count=1", branch, "--not", "--remotes"])
+        .unwrap_or_default()
+        .is_empty()
+}
+
+/// Branches whose newest PR was merged or closed, whose remote branch was
+/// deleted, or that the stack tracks but no longer exist. Branches that open
+/// PRs are based on, or with unpushed commits, are returned separately with
+/// the reason they are kept.
+fn find_candidates(config: &StackConfig, base: &str) -> Result<(Vec<Candidate>, Vec<Kept>)> {
+    let prs = github::list_recent_prs()?;
+    let open: HashSet<&str> = prs
+        .iter()
+        .filter(|pr| pr.state == "OPEN")
+        .map(|pr| pr.head_ref.as_str())
+        .collect();
+    let open_base_of = |branch: &str| {
+        prs.iter()
+            .find(|pr| pr.state == "OPEN" && pr.base_ref == branch)
+            .map(|pr| format!("open PR #{} is based on it", pr.number))
+    };
+    // The list is newest first, so keep the first PR seen for each branch
+    let mut newest: HashMap<&str, &PullRequest> = HashMap::new();
+    for pr in &prs {
+        newest.entry(pr.head_ref.as_str()).or_insert(pr);
+    }
+
+    let mut candidates = Vec::new();
+    let mut kept = Vec::new();
+    let locals = local_branches()?;
+    for branch in &locals {
+        if branch.name == base || open.contains(branch.name.as_str()) {
+            continue;
+        }
+        let pr = newest.get(branch.name.as_str());
+        let reason = match pr {
+            Some(pr) if pr.state != "OPEN" => {
+                format!("PR #{} {}", pr.number, pr.state.to_lowercase())
+            }
+            _ if branch.gone => format!("deleted on {}", git::push_remote()),
+            _ => continue,
+        };
+        if let Some(why) = open_base_of(&branch.name) {
+            kept.push(Kept {
+                name: branch.name.clone(),
+                why,
+            });
+            continue;
+        }
+        if unpushed(&branch.name, pr.map(|pr| pr.head_sha.as_str())) {
+            kept.push(Kept {
+                name: branch.name.clone(),
+                why: "it has unpushed commits".to_string(),
+            });
+            continue;
+        }
+        candidates.push(Candidate {
+            name: branch.name.clone(),
+            reason,
+            local: true,
+            remote: git::remote_tip(&branch.name).is_some(),
+            committed: branch.committed,
+        });
+    }
+
+    for branch in &config.branches {
+        if locals.iter().any(|local| local.name == branch.name) {
+            continue;
+        }
+        if let Some(why) = open_base_of(&branch.name) {
+            kept.push(Kept {
+                name: branch.name.clone(),
+                why,
+            });
+            continue;
+        }
+        candidates.push(Candidate {
+            name: branch.name.clone(),
+            reason: "tracked but no longer exists".to_string(),
+            local: false,
+            remote: git::remote_tip(&branch.name).is_some(),
+            committed: None,
+        });
+    }
+    Ok((candidates, kept))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Delete merged, closed and abandoned branches locally and on the remote,
+/// and drop them from the stack
+pub fn run(dry_run: bool, older_than: Option<Duration>) -> Result<()> {
+    if dry_run {
+        println!("{}", "[DRY RUN] Prune operations:".yellow().bold());
+    } else {
+        println!("{}", "Pruning stack branches...".green().bold());
+    }
+    println!();
+
+    let mut config = StackConfig::load().context("Failed to load configuration")?;
+    let base = config.base()?;
+    let current = git::current_branch()?;
+
+    // Prune remote-tracking branches first, so deleted remote branches show up
+    let remote = git::push_remote();
+    match git::run(&["fetch", "--prune", "--quiet", &remote]) {
+        Ok(_) => println!("{} Fetched {}", "✓".green(), remote),
+        Err(e) => println!("{} Failed to fetch {}: {}", "⚠".yellow(), remote, e),
+    }
+
+    let now = now();
+    let (candidates, kept) = find_candidates(&config, &base)?;
+    for branch in &kept {
+        println!(
+            "{} Keeping {}: {}",
+            "⚠".yellow(),
+            branch.name.cyan(),
+            branch.why
+        );
+    }
+    let mut candidates: Vec<Candidate> = candidates
+        .into_iter()
+        .filter(|candidate| match (older_than, candidate.committed) {
+            (Some(age), Some(committed)) => now.saturating_sub(committed) >= age.as_secs(),
+            _ => true,
+        })
+        .collect();
+
+    if let Some(index) = candidates
+        .iter()
+        .position(|candidate| candidate.name == current)
+    {
+        let candidate = candidates.remove(index);
+        println!(
+            "{} {} is checked out; switch branches to prune it ({})",
+            "○".dimmed(),
+            candidate.name,
+            candidate.reason
+        );
+    }
+
+    if candidates.is_empty() {
+        println!("{} Nothing to prune", "✓".green());
+        return Ok(());
+    }
+
+    let items: Vec<String> = candidates.iter().map(Candidate::describe).collect();
+    println!();
+    if dry_run {
+        println!("Would prune:");
+        for item in &items {
+            println!("  - {}", item.cyan());
+        }
+        println!();
+        println!("{}", "✓ Dry run complete".yellow());
+        return Ok(());
+    }
+
+    let selected = match MultiSelect::new()
+        .with_prompt("Branches to prune (space to select, enter to confirm)")
+        .items(&items)
+        .interact()
+    {
+        Ok(selected) => selected,
+        // Not a terminal or cancelled: delete nothing
+        Err(_) => {
+            println!("Cancelled.");
+            return Ok(());
+        }
+    };
+
+    if selected.is_empty() {
+        println!("Nothing selected.");
+        return Ok(());
+    }
+
+    println!();
+    let saved = config.clone();
+    let mut pruned = 0;
+    for candidate in selected.iter().map(|&index| &candidates[index]) {
+        let mut ok = true;
+        if candidate.local {
+            if let Err(e) = git::delete_branch(&candidate.name) {
+                println!("  {} Could not delete {}: {}", "✗".red(), candidate.name, e);
+                ok = false;
+            }
+        }
+        if candidate.remote {
+            if let Err(e) = git::delete_remote_branch(&candidate.name) {
+                println!(
+                    "  {} Could not delete {}/{}: {}",
+                    "✗".red(),
+                    remote,
+                    candidate.name,
+                    e
+                );
+                ok = false;
+            }
+        }
+        if ok {
+            config.remove_branch(&candidate.name);
+            pruned += 1;
+            println!("  {} Pruned {}", "✓".green(), candidate.name);
+        }
+    }
+
+    if config != saved {
+        config.save().context("Failed to save config")?;
+        push::share_stack();
+    }
+
+    println!();
+    println!(
+        "{}",
+        format!(
+            "✓ Pruned {} branch{}",
+            pruned,
+            if pruned == 1 { "" } else { "es" }
+        )
+        .green()
+        .bold()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("90m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert_eq!(parse_age("30"), parse_age("30d"));
+    }
+
+    #[test]
+    fn rejects_bad_and_overflowing_ages() {
+        for age in ["", "d", "3y", "-1d", "1.5d", "18446744073709551615w"] {
+            assert!(parse_age(age).is_err(), "{}", age);
+        }
+    }
+
+    #[test]
+    fn finds_unpushed_commits() {
+        let repo = Fixture::new();
+        repo.branch("feature");
+        let pushed = repo.commit("pushed");
+        repo.git(&["update-ref", "refs/remotes/origin/feature", &pushed]);
+        assert!(!unpushed("feature", None));
+
+        repo.commit("local only");
+        assert!(unpushed("feature", None));
+
+        // The remote branch was deleted after the PR merged
+        repo.git(&["update-ref", "-d", "refs/remotes/origin/feature"]);
+        let head = repo.git(&["rev-parse", "feature"]);
+        assert!(!unpushed("feature", Some(&head)));
+        assert!(unpushed("feature", Some(&pushed)));
+        assert!(unpushed("feature", Some("")));
+    }
+}
@@ -1,6 +1,274 @@
-use crate::{git, progress, stack::StackConfig};
+use crate::{
+    error::FlowError,
+    git, progress,
+    stack::StackConfig,
+    storage::{self, Storage},
+    validation,
+};
 use anyhow::{Context, Result};
 use colored::Colorize;
+use dialoguer::Select;
+use std::thread;
+
+/// Outcome of pushing one branch
+pub struct PushResult {
+    pub branch: String,
+    pub result: Result<()>,
+}
+
+/// Branches whose local tip differs from their remote-tracking branch
+pub fn changed_branches<'a>(branches: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    branches
+        .into_iter()
+        .filter(|branch| git::rev_parse(branch) != git::remote_tip(branch))
+        .map(String::from)
+        .collect()
+}
+
+/// What to do with a branch someone else pushed commits to
+enum Divergence {
+    Incorporate,
+    Overwrite,
+    Skip,
+}
+
+fn ask_divergence(branch: &str, theirs: &[String]) -> Divergence {
+    println!();
+    println!(
+        "{} {} has commits on {} that you don't have:",
+        "⚠".yellow(),
+        branch.cyan(),
+        git::push_remote()
+    );
+    for commit in theirs {
+        println!("    {}", commit.dimmed());
+    }
+
+    let choice = Select::new()
+        .with_prompt("What do you want to do?")
+        .items(&[
+            "Rebase their commits onto my branch",
+            "Overwrite the remote branch",
+            "Skip this branch",
+        ])
+        .default(0)
+        .interact();
+
+    match choice {
+        Ok(0) => Divergence::Incorporate,
+        Ok(1) => Divergence::Overwrite,
+        // Not a terminal or cancelled: leave the branch alone
+        _ => Divergence::Skip,
+    }
+}
+
+/// Replay commits pushed by someone else (`since..theirs`) on top of the local branch
+fn incorporate_remote_commits(branch: &str, since: &str, theirs: &str) -> Result<()> {
+    git::run(&["fetch", &git::push_remote(), branch])?;
+
+    let original = git::current_branch()?;
+    git::run(&["checkout", branch])?;
+    if let Err(e) = git::run(&["cherry-pick", &format!("{}..{}", since, theirs)]) {
+        anyhow::bail!(
+            "{}\nResolve the conflicts and run `git cherry-pick --continue`, then push again",
+            e
+        );
+    }
+    git::run(&["checkout", &original])?;
+    Ok(())
+}
+
+/// Work out how to push each branch. The remote is compared with the commit
+/// gh-flow last pushed, so commits a teammate pushed in the meantime are
+/// noticed instead of silently overwritten.
+fn plan_pushes(
+    config: &StackConfig,
+    branches: &[String],
+    force: bool,
+) -> Result<(Vec<git::PushSpec>, Vec<PushResult>)> {
+    let remote = git::ls_remote(branches)?;
+    let mut specs = Vec::new();
+    let mut skipped = Vec::new();
+
+    for branch in branches {
+        let recorded = config.get_branch(branch).and_then(|b| b.pushed_sha.clone());
+        let remote_sha = remote.get(branch).cloned();
+        let local = git::rev_parse(branch).unwrap_or_default();
+
+        let mut spec = git::PushSpec {
+            branch: branch.clone(),
+            force,
+            expected: None,
+        };
+
+        spec.expected = match (&recorded, &remote_sha) {
+            // Never pushed by gh-flow: lease against the remote-tracking ref
+            (None, _) => None,
+            // Deleted on the remote: it must stay deleted until we push
+            (Some(_), None) => Some(String::new()),
+            // Untouched since our last push: lease against what we pushed
+            (Some(recorded), Some(remote_sha)) if recorded == remote_sha => Some(recorded.clone()),
+            // Their commits are already part of the local branch
+            (Some(_), Some(remote_sha))
+                if *remote_sha == local || git::is_ancestor(remote_sha, &local) =>
+            {
+                Some(remote_sha.clone())
+            }
+            (Some(recorded), Some(remote_sha)) => {
+                git::run(&["fetch", &git::push_remote(), branch])?;
+                let theirs = git::commit_subjects(&format!("{}..{}", recorded, remote_sha))
+                    .unwrap_or_default();
+
+                match ask_divergence(branch, &theirs) {
+                    Divergence::Incorporate => {
+                        incorporate_remote_commits(branch, recorded, remote_sha)?;
+                        println!(
+                            "{} Rebased their commits onto {}",
+                            "✓".green(),
+                            branch.cyan()
+                        );
+                        // Our history now contains their changes, but not their commits
+                        spec.force = true;
+                    }
+                    Divergence::Overwrite => spec.force = true,
+                    Divergence::Skip => {
+                        skipped.push(PushResult {
+                            branch: branch.clone(),
+                            result: Err(anyhow::anyhow!(
+                                "skipped, the remote has commits you don't have"
+                            )),
+                        });
+                        continue;
+                    }
+                }
+                // Replace exactly the commits that were looked at, nothing pushed since
+                Some(remote_sha.clone())
+            }
+        };
+
+        specs.push(spec);
+    }
+
+    Ok((specs, skipped))
+}
+
+/// Whether git refused `--atomic` because the remote can't do it. A rejected
+/// atomic push ("atomic push failed") must not be retried branch by branch.
+fn atomic_unsupported(stderr: &str) -> bool {
+    stderr.contains("does not support --atomic push")
+}
+
+/// Push branches atomically, so the remote never ends up with half a stack.
+/// Falls back to pushing in parallel when the remote doesn't support `--atomic`.
+/// Records the pushed commit of every successful branch in `config`.
+pub fn push_branches(
+    config: &mut StackConfig,
+    branches: &[String],
+    force: bool,
+) -> Result<Vec<PushResult>> {
+    if branches.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let (specs, mut results) = plan_pushes(config, branches, force)?;
+
+    let spinner = progress::create_spinner(&format!("Pushing {} branches atomically", specs.len()));
+    let atomic = if specs.is_empty() {
+        Ok(())
+    } else {
+        git::push_atomic(&specs)
+    };
+    spinner.finish_and_clear();
+
+    let pushed = match atomic {
+        Ok(_) => specs
+            .iter()
+            .map(|spec| PushResult {
+                branch: spec.branch.clone(),
+                result: Ok(()),
+            })
+            .collect(),
+        Err(e)
+            if matches!(
+                e.downcast_ref::<FlowError>(),
+                Some(FlowError::Git { stderr, .. }) if atomic_unsupported(stderr)
+            ) =>
+        {
+            println!(
+                "{} Remote doesn't support atomic pushes, pushing in parallel",
+                "⚠".yellow()
+            );
+            push_parallel(&specs)
+        }
+        Err(e) => {
+            // Nothing was pushed; report the rejection for every branch
+            let message = e.to_string();
+            specs
+                .iter()
+                .map(|spec| PushResult {
+                    branch: spec.branch.clone(),
+                    result: Err(anyhow::anyhow!("{}", message.trim())),
+                })
+                .collect()
+        }
+    };
+
+    for result in &pushed {
+        if result.result.is_ok() {
+            let sha = git::rev_parse(&result.branch);
+            if let Some(info) = config.get_branch_mut(&result.branch) {
+                info.pushed_sha = sha;
+            }
+        }
+    }
+
+    results.extend(pushed);
+    Ok(results)
+}
+
+/// Push the stack ref when stack metadata is shared through the remote
+pub fn share_stack() {
+    if Storage::current() != Storage::Refs {
+        return;
+    }
+    match storage::push() {
+        Ok(_) => println!(
+            "{} Shared stack metadata on {}",
+            "✓".green(),
+            git::push_remote()
+        ),
+        Err(e) => println!("{} Failed to share stack metadata: {}", "⚠".yellow(), e),
+    }
+}
+
+fn push_parallel(specs: &[git::PushSpec]) -> Vec<PushResult> {
+    let progress_bar = progress::create_progress_bar(specs.len() as u64, "Pushing branches");
+
+    let results = thread::scope(|scope| {
+        let handles: Vec<_> = specs
+            .iter()
+            .map(|spec| {
+                let progress_bar = progress_bar.clone();
+                scope.spawn(move || {
+                    let result = git::push(spec);
+                    progress_bar.inc(1);
+                    PushResult {
+                        branch: spec.branch.clone(),
+                        result,
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("push thread panicked"))
+            .collect()
+    });
+
+    progress_bar.finish_and_clear();
+    results
+}
 
 pub fn run(force: bool) -> Result<()> {
     println!("{}", "Pushing stack branches...".green().bold());
@@ -10,8 +278,12 @@ pub fn run(force: bool) -> Result<()> {
     }
     println!();
 
-    // Load configuration
-    let config = StackConfig::load().context("Failed to load configuration")?;
+    // Uncommitted work is not part of what gets pushed
+    validation::warn_uncommitted_changes()?;
+
+    // Load configuration; only the stack HEAD is on is pushed
+    let mut saved = StackConfig::load().context("Failed to load configuration")?;
+    let mut config = saved.current_stack()?;
 
     if config.branches.is_empty() {
         println!(
@@ -21,71 +293,81 @@ pub fn run(force: bool) -> Result<()> {
         return Ok(());
     }
 
-    // Push each branch
-    let mut success_count = 0;
-    let mut error_count = 0;
-
-    let total_branches = config.branches.len() as u64;
-    let progress_bar = progress::create_progress_bar(total_branches, "Pushing branches");
-
+    // Skip branches that don't exist or are already up to date
+    let mut existing = Vec::new();
     for branch_info in &config.branches {
-        progress_bar.set_message(format!("Pushing {}", branch_info.name));
-
-        // Check if branch exists
-        if !git::branch_exists(&branch_info.name)? {
-            progress_bar.println(format!(
+        if git::branch_exists(&branch_info.name)? {
+            existing.push(branch_info.name.clone());
+        } else {
+            println!(
                 "{} {} - skipped (branch doesn't exist)",
                 "⚠".yellow(),
                 branch_info.name
-            ));
-            progress_bar.inc(1);
-            continue;
+            );
         }
+    }
+
+    let to_push = changed_branches(existing.iter().map(String::as_str));
+    for branch in existing.iter().filter(|b| !to_push.contains(b)) {
+        println!("{} {} - up to date", "✓".dimmed(), branch);
+    }
 
-        // Push the branch
-        match git::push(&branch_info.name, force) {
+    let results = push_branches(&mut config, &to_push, force)?;
+    saved
+        .save_stack(&config)
+        .context("Failed to save configuration")?;
+
+    let mut success_count = 0;
+    let mut error_count = 0;
+    for PushResult { branch, result } in &results {
+        match result {
             Ok(_) => {
-                progress_bar.println(format!(
-                    "{} Pushed {}",
-                    "✓".green(),
-                    branch_info.name.cyan()
-                ));
+                println!("{} Pushed {}", "✓".green(), branch.cyan());
                 success_count += 1;
             }
             Err(e) => {
-                progress_bar.println(format!(
-                    "{} Failed to push {}: {}",
-                    "✗".red(),
-                    branch_info.name.cyan(),
-                    e
-                ));
+                println!("{} Failed to push {}: {}", "✗".red(), branch.cyan(), e);
                 error_count += 1;
             }
         }
-        progress_bar.inc(1);
     }
 
-    progress_bar.finish_and_clear();
+    share_stack();
+
+    let up_to_date = existing.len() - to_push.len();
 
     println!();
     if error_count == 0 {
         println!(
-            "{} All {} branches pushed successfully",
+            "{} {} branches pushed, {} up to date",
             "✓".green(),
-            success_count
+            success_count,
+            up_to_date
         );
     } else {
         println!(
-            "{} {} branches pushed, {} failed",
-            if error_count > 0 {
-                "⚠".yellow()
-            } else {
-                "✓".green()
-            },
+            "{} {} branches pushed, {} failed, {} up to date",
+            "⚠".yellow(),
             success_count,
-            error_count
+            error_count,
+            up_to_date
         );
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_only_when_the_remote_lacks_atomic_support() {
+        assert!(atomic_unsupported(
+            "fatal: the receiving end does not support --atomic push\n"
+        ));
+        let rejected = "To origin\n ! [rejected] a -> a (stale info)\nerror: failed to push some refs to 'origin'\n\
+                        error: atomic push failed for ref refs/heads/a. status: 2\n";
+        assert!(!atomic_unsupported(rejected));
+    }
+}
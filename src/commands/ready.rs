@@ -0,0 +1,156 @@
+use crate::{
+    git, github, settings,
+    stack::{BranchInfo, DraftPolicy, StackConfig},
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+/// PRs of a stack's branches that are still open, bottom first, with their draft flag
+fn open_prs(branches: &[BranchInfo]) -> Vec<(String, u32, bool)> {
+    branches
+        .iter()
+        .filter_map(|b| b.pr_number.map(|n| (b.name.clone(), n)))
+        .filter_map(|(name, n)| {
+            let details = github::get_pr_details(n).ok()?;
+            (details.state == "OPEN").then_some((name, n, details.is_draft))
+        })
+        .collect()
+}
+
+fn mark_ready(branch: &str, pr_number: u32) -> bool {
+    match github::mark_pr_ready(pr_number) {
+        Ok(_) => {
+            println!(
+                "  {} PR #{} ({}) ready for review",
+                "✓".green(),
+                pr_number,
+                branch.cyan()
+            );
+            true
+        }
+        Err(e) => {
+            println!(
+                "  {} PR #{} ({}): {}",
+                "✗".red(),
+                pr_number,
+                branch.cyan(),
+                e
+            );
+            false
+        }
+    }
+}
+
+/// With the bottom-ready policy, mark the bottom-most open PR of `stack` ready
+/// for review. Called after PRs below it may have merged (`sync`, `merge`).
+pub fn apply_draft_policy(stack: &StackConfig) -> Result<()> {
+    if stack.pr.draft_policy != DraftPolicy::BottomReady {
+        return Ok(());
+    }
+
+    if let Some((branch, pr_number, true)) = open_prs(&stack.branches).into_iter().next() {
+        mark_ready(&branch, pr_number);
+    }
+
+    Ok(())
+}
+
+/// Make a stack match the bottom-ready policy: bottom PR ready, the rest drafts
+fn enforce_bottom_ready(branches: &[BranchInfo]) {
+    for (idx, (branch, pr_number, is_draft)) in open_prs(branches).into_iter().enumerate() {
+        if idx == 0 {
+            if is_draft {
+                mark_ready(&branch, pr_number);
+            }
+        } else if !is_draft {
+            match github::mark_pr_draft(pr_number) {
+                Ok(_) => println!(
+                    "  {} PR #{} ({}) converted to draft",
+                    "✓".green(),
+                    pr_number,
+                    branch.cyan()
+                ),
+                Err(e) => println!(
+                    "  {} PR #{} ({}): {}",
+                    "✗".red(),
+                    pr_number,
+                    branch.cyan(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+pub fn run(all: bool, bottom: bool, policy: Option<DraftPolicy>) -> Result<()> {
+    println!("{}", "Marking PRs ready for review...".green().bold());
+    println!();
+
+    let mut config = StackConfig::load().context("Failed to load configuration")?;
+
+    if config.branches.is_empty() {
+        println!(
+            "{}",
+            "No branches in stack. Run `gh flow init` first.".yellow()
+        );
+        return Ok(());
+    }
+
+    if let Some(policy) = policy {
+        let value = serde_json::to_value(policy)?;
+        config.set_setting("pr.draft-policy", Some(value.clone()))?;
+        config.save().context("Failed to save configuration")?;
+        println!(
+            "{} Draft policy set to {}",
+            "✓".green(),
+            settings::display_value(&value).cyan()
+        );
+
+        if policy == DraftPolicy::BottomReady {
+            for stack in config.stacks() {
+                enforce_bottom_ready(&stack);
+            }
+        }
+        if !all && !bottom {
+            return Ok(());
+        }
+    }
+
+    let prs = open_prs(&config.current_stack()?.branches);
+    let targets: Vec<_> = if all {
+        prs
+    } else if bottom {
+        prs.into_iter().take(1).collect()
+    } else {
+        let current = git::current_branch()?;
+        let target: Vec<_> = prs
+            .into_iter()
+            .filter(|(name, _, _)| *name == current)
+            .collect();
+        if target.is_empty() {
+            anyhow::bail!("No open PR for branch '{}' in the stack", current);
+        }
+        target
+    };
+
+    let mut ready_count = 0;
+    for (branch, pr_number, is_draft) in targets {
+        if !is_draft {
+            println!(
+                "  {} PR #{} ({}) already ready",
+                "✓".dimmed(),
+                pr_number,
+                branch.cyan()
+            );
+            continue;
+        }
+        if mark_ready(&branch, pr_number) {
+            ready_count += 1;
+        }
+    }
+
+    println!();
+    println!("{} {} PRs marked ready", "✓".green(), ready_count);
+
+    Ok(())
+}
@@ -1,23 +1,26 @@
 use anyhow::{bail, Result};
 use colored::*;
 use dialoguer::{Confirm, MultiSelect};
-use std::process::Command;
 
-use crate::stack::{BranchInfo, StackConfig};
+use crate::stack::StackConfig;
+use crate::{autostash, github};
 
-pub fn run(interactive: bool) -> Result<()> {
-    let config = StackConfig::load()?;
+pub fn run(interactive: bool, autostash: Option<bool>) -> Result<()> {
+    autostash::run(autostash::enabled(autostash)?, || split(interactive))
+}
+
+fn split(interactive: bool) -> Result<()> {
+    let mut config = StackConfig::load()?;
+    let stack = config.current_stack()?;
 
-    if config.branches.len() < 2 {
+    if stack.branches.len() < 2 {
         bail!("Stack needs at least 2 branches to split");
     }
 
     println!("{}", "✂️  Split Stack".cyan().bold());
     println!();
 
-    let branch_names: Vec<&str> = config.branches.iter()
-        .map(|b| b.name.as_str())
-        .collect();
+    let branch_names: Vec<&str> = stack.branches.iter().map(|b| b.name.as_str()).collect();
 
     let selected = if interactive {
         let selection = MultiSelect::new()
@@ -32,8 +35,8 @@ pub fn run(interactive: bool) -> Result<()> {
         selection
     } else {
         // Non-interactive: split at the middle
-        let mid = config.branches.len() / 2;
-        (mid..config.branches.len()).collect()
+        let mid = stack.branches.len() / 2;
+        (mid..stack.branches.len()).collect()
     };
 
     println!();
@@ -52,42 +55,33 @@ pub fn run(interactive: bool) -> Result<()> {
     }
 
     // Create new stack with selected branches
-    let mut new_branches: Vec<BranchInfo> = Vec::new();
-    let mut remaining_branches: Vec<BranchInfo> = Vec::new();
-
-    for (idx, branch) in config.branches.into_iter().enumerate() {
-        if selected.contains(&idx) {
-            new_branches.push(branch);
-        } else {
-            remaining_branches.push(branch);
+    let new_branches: Vec<_> = selected.iter().map(|&idx| &stack.branches[idx]).collect();
+
+    // Base the first branch of the new stack on the base branch, which keeps
+    // tracking the split branches as a stack of their own
+    let original_count = stack.branches.len() - new_branches.len();
+    let base_branch = config.base_branch.clone();
+    if let Some(first) = new_branches.first() {
+        if let Some(branch) = config.get_branch_mut(&first.name) {
+            branch.parent = base_branch;
         }
     }
-
-    // Update parent of first branch in new stack to point to base
-    if let Some(first) = new_branches.first_mut() {
-        first.parent = config.base_branch.clone();
-    }
-
-    // Save original stack with remaining branches
-    let original_config = StackConfig {
-        base_branch: config.base_branch.clone(),
-        branches: remaining_branches,
-    };
-    original_config.save()?;
+    config.save()?;
 
     // Update the PRs to point to base branch
     for branch in &new_branches {
         if let Some(pr_number) = branch.pr_number {
-            println!("  Updating PR #{} base to {}...", pr_number, config.base_branch);
-            let _ = Command::new("gh")
-                .args(["pr", "edit", &pr_number.to_string(), "--base", &config.base_branch])
-                .output();
+            println!(
+                "  Updating PR #{} base to {}...",
+                pr_number, config.base_branch
+            );
+            let _ = github::update_pr_base(pr_number, &config.base_branch);
         }
     }
 
     println!();
     println!("{} Stack split successfully!", "✓".green());
-    println!("  Original stack: {} branches", original_config.branches.len());
+    println!("  Original stack: {} branches", original_count);
     println!("  Split branches retargeted to {}", config.base_branch);
 
     Ok(())
@@ -7,10 +7,16 @@ pub fn run() -> Result<()> {
     println!();
 
     // Auto-discover branch chain
-    let config = StackConfig::discover("main").context("Failed to discover branches")?;
+    let saved = StackConfig::load().context("Failed to load configuration")?;
+    let base = saved.base()?;
+    let mut config = StackConfig::discover(&base).context("Failed to discover branches")?;
+    config.keep_saved_state(&saved);
 
     if config.branches.is_empty() {
-        println!("{}", "No branches found from main to current branch.".yellow());
+        println!(
+            "{}",
+            format!("No branches found from {} to current branch.", base).yellow()
+        );
         return Ok(());
     }
 
@@ -0,0 +1,412 @@
+use super::pr::{self, MetadataOptions, MetadataPlan, SectionPlacement};
+use super::push;
+use crate::{
+    git, github,
+    stack::{DraftPolicy, StackConfig},
+    validation,
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::HashMap;
+
+/// What happened to one branch during submit
+struct BranchResult {
+    branch: String,
+    pr_number: Option<u32>,
+    actions: Vec<String>,
+    failed: bool,
+    /// Its PR was merged or closed, so it is left alone
+    done: bool,
+}
+
+impl BranchResult {
+    fn record(&mut self, action: String) {
+        self.actions.push(action);
+    }
+
+    fn fail(&mut self, action: &str, err: impl std::fmt::Display) {
+        println!("  {} {} {}: {}", "✗".red(), self.branch.cyan(), action, err);
+        self.actions.push(format!("{} failed", action));
+        self.failed = true;
+    }
+}
+
+fn report(dry_run: bool, branch: &str, action: &str) {
+    if dry_run {
+        println!("  {} {} {}", "↻".yellow(), branch.cyan(), action);
+    } else {
+        println!("  {} {} {}", "✓".green(), branch.cyan(), action);
+    }
+}
+
+pub fn run(draft: bool, dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!("{}", "[DRY RUN] Submit operations:".yellow().bold());
+    } else {
+        println!("{}", "Submitting stack...".green().bold());
+    }
+    println!();
+
+    // Uncommitted work is not part of what gets pushed
+    validation::warn_uncommitted_changes()?;
+
+    // Only the stack HEAD is on is submitted
+    let mut saved = StackConfig::load().context("Failed to load configuration")?;
+    let mut config = saved.current_stack()?;
+
+    if config.branches.is_empty() {
+        println!(
+            "{}",
+            "No branches in stack. Run `gh flow init` first.".yellow()
+        );
+        return Ok(());
+    }
+
+    let mut results: Vec<BranchResult> = config
+        .branches
+        .iter()
+        .map(|b| BranchResult {
+            branch: b.name.clone(),
+            pr_number: None,
+            actions: Vec::new(),
+            failed: false,
+            done: false,
+        })
+        .collect();
+
+    // One query for all open PRs
+    let open_prs: HashMap<String, github::PullRequest> = github::list_open_prs()?
+        .into_iter()
+        .map(|pr| (pr.head_ref.clone(), pr))
+        .collect();
+
+    // A branch whose saved PR was merged or closed is neither pushed, which
+    // would bring back its deleted remote branch, nor given a second PR
+    for (branch, result) in config.branches.iter().zip(results.iter_mut()) {
+        let Some(number) = branch.pr_number else {
+            continue;
+        };
+        if open_prs.get(&branch.name).map(|pr| pr.number) == Some(number) {
+            continue;
+        }
+        match github::get_pr_by_number(number) {
+            Ok(pr) if pr.state != "OPEN" => {
+                let state = pr.state.to_lowercase();
+                let hint = if pr.state == "MERGED" {
+                    "run `gh flow sync` to drop it from the stack"
+                } else {
+                    "reopen it, or run `gh flow prune` to drop the branch"
+                };
+                println!(
+                    "{} {} PR #{} is {}; {}",
+                    "⚠".yellow(),
+                    branch.name.cyan(),
+                    number,
+                    state,
+                    hint
+                );
+                result.pr_number = Some(number);
+                result.record(format!("skipped (PR {})", state));
+                result.done = true;
+            }
+            Ok(_) => {}
+            Err(e) => result.fail("look up PR", e),
+        }
+    }
+
+    // Step 1: Push branches whose local tip differs from the remote, atomically
+    println!("{}", "Pushing branches...".cyan());
+    for result in results.iter_mut().filter(|r| !r.failed && !r.done) {
+        if !git::branch_exists(&result.branch)? {
+            println!(
+                "  {} {} - branch doesn't exist",
+                "⚠".yellow(),
+                result.branch
+            );
+            result.record("skipped (branch doesn't exist)".to_string());
+            result.failed = true;
+        }
+    }
+
+    let to_push = push::changed_branches(
+        results
+            .iter()
+            .filter(|r| !r.failed && !r.done)
+            .map(|r| r.branch.as_str()),
+    );
+
+    if dry_run {
+        for result in results.iter_mut().filter(|r| to_push.contains(&r.branch)) {
+            report(true, &result.branch, "would be pushed");
+            result.record("push".to_string());
+        }
+    } else {
+        for pushed in push::push_branches(&mut config, &to_push, true)? {
+            let Some(result) = results.iter_mut().find(|r| r.branch == pushed.branch) else {
+                continue;
+            };
+            match pushed.result {
+                Ok(_) => {
+                    report(false, &result.branch, "pushed");
+                    result.record("pushed".to_string());
+                }
+                Err(e) => result.fail("push", e),
+            }
+        }
+    }
+
+    for result in results
+        .iter()
+        .filter(|r| !r.failed && !r.done && !to_push.contains(&r.branch))
+    {
+        println!("  {} {} up to date", "✓".dimmed(), result.branch);
+    }
+    println!();
+
+    // Step 2: Create missing PRs and fix wrong bases
+    println!("{}", "Syncing PRs...".cyan());
+    let metadata_plan = MetadataPlan::new(&config, &MetadataOptions::default());
+    let statuses = pr::fetch_pr_statuses(&config);
+    let bottom_ready = config.pr.draft_policy == DraftPolicy::BottomReady;
+    let comment = config.pr.stack_comment;
+    let mut open_below = false;
+
+    for (i, result) in results.iter_mut().enumerate() {
+        if result.failed || result.done {
+            continue;
+        }
+        let branch_name = config.branches[i].name.clone();
+        let parent_name = config.branches[i].parent.clone();
+
+        if let Some(existing) = open_prs.get(&branch_name) {
+            config.branches[i].pr_number = Some(existing.number);
+            result.pr_number = Some(existing.number);
+            open_below = true;
+
+            if existing.base_ref != parent_name {
+                let action = format!("base {} → {}", existing.base_ref, parent_name);
+                if dry_run {
+                    report(
+                        true,
+                        &branch_name,
+                        &format!("PR #{} {}", existing.number, action),
+                    );
+                    result.record(format!("retarget ({})", action));
+                } else {
+                    match github::update_pr_base(existing.number, &parent_name) {
+                        Ok(_) => {
+                            report(
+                                false,
+                                &branch_name,
+                                &format!("PR #{} {}", existing.number, action),
+                            );
+                            result.record(format!("retargeted ({})", action));
+                        }
+                        Err(e) => result.fail("retarget", e),
+                    }
+                }
+            }
+            continue;
+        }
+
+        let title = pr::generate_title(&config, &branch_name);
+        let as_draft = draft || (bottom_ready && open_below);
+        open_below = true;
+
+        if dry_run {
+            report(
+                true,
+                &branch_name,
+                &format!(
+                    "would get a {}PR: {}",
+                    if as_draft { "draft " } else { "" },
+                    title
+                ),
+            );
+            result.record("create PR".to_string());
+            continue;
+        }
+
+        let (metadata, _) = metadata_plan.for_branch(&config.branches[i]);
+        // In comment mode the stack goes into a comment in step 3
+        let body = if comment {
+            String::new()
+        } else {
+            match pr::generate_stack_section(&config, &branch_name, &title, &statuses) {
+                Ok(body) => body,
+                Err(e) => {
+                    result.fail("create PR", format!("{:#}", e));
+                    continue;
+                }
+            }
+        };
+
+        match github::create_pr(
+            &branch_name,
+            &parent_name,
+            &title,
+            &body,
+            as_draft,
+            &metadata,
+        ) {
+            Ok(pr_number) => {
+                report(false, &branch_name, &format!("PR #{} created", pr_number));
+                config.branches[i].pr_number = Some(pr_number);
+                result.pr_number = Some(pr_number);
+                result.record("created PR".to_string());
+            }
+            Err(e) => result.fail("create PR", e),
+        }
+    }
+
+    if !dry_run {
+        saved
+            .save_stack(&config)
+            .context("Failed to save configuration")?;
+        push::share_stack();
+    }
+    println!();
+
+    // Step 3: Refresh the stack section of every PR, now that all numbers are known
+    println!("{}", "Refreshing PR descriptions...".cyan());
+    let statuses = pr::fetch_pr_statuses(&config);
+    for result in results.iter_mut().filter(|r| !r.done) {
+        let Some(pr_number) = result.pr_number else {
+            continue;
+        };
+
+        let content = match github::get_pr_content(pr_number) {
+            Ok(content) => content,
+            Err(e) => {
+                result.fail("update description", e);
+                continue;
+            }
+        };
+        let section =
+            match pr::generate_stack_section(&config, &result.branch, &content.title, &statuses) {
+                Ok(section) => section,
+                Err(e) => {
+                    result.fail("update description", format!("{:#}", e));
+                    continue;
+                }
+            };
+        let written = pr::write_stack_section(
+            pr_number,
+            &content.body,
+            &section,
+            SectionPlacement::Bottom,
+            comment,
+            dry_run,
+        );
+
+        match written {
+            Ok(false) => {}
+            Ok(true) if dry_run => {
+                report(
+                    true,
+                    &result.branch,
+                    &format!("PR #{} description would be updated", pr_number),
+                );
+                result.record("update description".to_string());
+            }
+            Ok(true) => {
+                report(
+                    false,
+                    &result.branch,
+                    &format!("PR #{} description updated", pr_number),
+                );
+                if !result.actions.iter().any(|a| a == "created PR") {
+                    result.record("updated description".to_string());
+                }
+            }
+            Err(e) => result.fail("update description", e),
+        }
+    }
+    println!();
+
+    // Summary
+    let urls: HashMap<u32, String> = if dry_run {
+        open_prs
+            .values()
+            .map(|pr| (pr.number, pr.url.clone()))
+            .collect()
+    } else {
+        github::list_open_prs()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|pr| (pr.number, pr.url))
+            .collect()
+    };
+    print_summary(&results, &urls);
+
+    println!();
+    let failed = results.iter().filter(|r| r.failed).count();
+    if dry_run {
+        println!("{}", "✓ Dry run complete".yellow());
+    } else if failed == 0 {
+        println!("{}", "✓ Stack submitted".green().bold());
+    } else {
+        println!(
+            "{} Stack submitted, {} branches need attention",
+            "⚠".yellow(),
+            failed
+        );
+    }
+
+    Ok(())
+}
+
+fn print_summary(results: &[BranchResult], urls: &HashMap<u32, String>) {
+    let rows: Vec<[String; 4]> = results
+        .iter()
+        .map(|r| {
+            let pr = r
+                .pr_number
+                .map(|n| format!("#{}", n))
+                .unwrap_or_else(|| "-".to_string());
+            let actions = if r.actions.is_empty() {
+                "up to date".to_string()
+            } else {
+                r.actions.join(", ")
+            };
+            let url = r
+                .pr_number
+                .and_then(|n| urls.get(&n).cloned())
+                .unwrap_or_default();
+            [r.branch.clone(), pr, actions, url]
+        })
+        .collect();
+
+    let headers = ["Branch", "PR", "Actions", "URL"];
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|col| {
+            rows.iter()
+                .map(|row| row[col].chars().count())
+                .chain(std::iter::once(headers[col].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let header = headers
+        .iter()
+        .zip(&widths)
+        .map(|(h, w)| format!("{:<w$}", h, w = w))
+        .collect::<Vec<_>>()
+        .join("  ");
+    println!("{}", header.trim_end().bold());
+
+    for (row, result) in rows.iter().zip(results) {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, w)| format!("{:<w$}", cell, w = w))
+            .collect::<Vec<_>>()
+            .join("  ");
+        if result.failed {
+            println!("{}", line.trim_end().red());
+        } else {
+            println!("{}", line.trim_end());
+        }
+    }
+}
@@ -1,8 +1,29 @@
-use crate::{git, github, stack::StackConfig};
+use super::{push, ready};
+use crate::{
+    autostash, git, github,
+    stack::{BranchInfo, StackConfig},
+    storage::{self, PullOutcome, Storage},
+    worktree::{self, Scratch},
+};
 use anyhow::{Context, Result};
 use colored::Colorize;
+use dialoguer::Confirm;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 
-pub fn run(dry_run: bool, _wait_ci: bool) -> Result<()> {
+pub fn run(all: bool, dry_run: bool, wait_ci: bool, autostash: Option<bool>) -> Result<()> {
+    if all {
+        return sync_all(dry_run);
+    }
+    if dry_run {
+        return sync_stack(true, wait_ci);
+    }
+    autostash::run(autostash::enabled(autostash)?, || {
+        sync_stack(false, wait_ci)
+    })
+}
+
+fn sync_stack(dry_run: bool, _wait_ci: bool) -> Result<()> {
     if dry_run {
         println!("{}", "[DRY RUN] Sync operations:".yellow().bold());
     } else {
@@ -10,28 +31,144 @@ pub fn run(dry_run: bool, _wait_ci: bool) -> Result<()> {
     }
     println!();
 
-    let base_branch = "main";
+    let base_branch = StackConfig::load()
+        .context("Failed to load configuration")?
+        .base()?;
+    let base_branch = base_branch.as_str();
+    let remote_base = format!("{}/{}", git::upstream_remote(), base_branch);
+    let original_branch = git::current_branch()?;
+
+    // Step 1: Fetch and check the base branch
+    fetch_base(dry_run, base_branch, &remote_base)?;
+
+    // Loaded after fetching, which may have merged teammates' changes
+    let mut saved = StackConfig::load().context("Failed to load configuration")?;
+
+    // Step 2: Auto-discover branch chain
+    println!("{}", "Discovering branch chain...".cyan());
+    let mut config = StackConfig::discover(base_branch).context("Failed to discover branches")?;
+    config.keep_saved_state(&saved);
+
+    if config.branches.is_empty() {
+        println!("  {} No branches found", "⚠".yellow());
+        return Ok(());
+    }
+
+    println!("  {} (base)", base_branch.green());
+    for (i, branch) in config.branches.iter().enumerate() {
+        let is_last = i == config.branches.len() - 1;
+        if is_last {
+            println!("    └─ {}  ← current", branch.name.cyan().bold());
+        } else {
+            println!("    └─ {}", branch.name);
+        }
+    }
+    println!();
+
+    // Step 3: Collapse branches that already landed in the base
+    println!("{}", "Checking for merged branches...".cyan());
+    let landed_in = newest_base(base_branch, &remote_base);
+    let merged = find_merged(&config.branches, landed_in);
+
+    if merged.is_empty() {
+        println!("  {} No merged branches", "✓".green());
+    } else {
+        for branch in &merged {
+            println!("  {} {} ({})", "✓".green(), branch.name.cyan(), branch.how);
+        }
+        if dry_run {
+            for branch in &merged {
+                for child in config.remove_branch(&branch.name) {
+                    let parent = config
+                        .get_branch(&child)
+                        .map(|b| b.parent.clone())
+                        .unwrap_or_default();
+                    println!("  {} Would rebase {} onto {}", "↻".yellow(), child, parent);
+                }
+            }
+        } else {
+            collapse(&mut config, &merged)?;
+        }
+    }
+    println!();
+
+    // Step 4: Sync PR bases
+    println!("{}", "Syncing PR targets...".cyan());
+
+    sync_pr_bases(&config, dry_run)?;
+
+    // Promote the next PR once the ones below it have merged
+    if !dry_run {
+        ready::apply_draft_policy(&config)?;
+    }
+
+    // Save the chain alongside the other tracked stacks
+    if !dry_run {
+        for branch in &merged {
+            saved.remove_branch(&branch.name);
+        }
+        saved.track(&config);
+        saved.save().context("Failed to save config")?;
+        push::share_stack();
+    }
+
+    if !dry_run && !merged.is_empty() {
+        offer_delete(&merged, &original_branch);
+    }
+
+    println!();
+    if dry_run {
+        println!("{}", "✓ Dry run complete".yellow());
+    } else {
+        println!("{}", "✓ Stack synchronized".green().bold());
+    }
+
+    Ok(())
+}
 
-    // Step 1: Fetch and check main branch
+/// Fetch the base branch and the shared stack, and fast-forward the local base
+fn fetch_base(dry_run: bool, base_branch: &str, remote_base: &str) -> Result<()> {
     println!("{}", "Fetching remote...".cyan());
 
     if !dry_run {
-        match git::run(&["fetch", "origin", base_branch]) {
-            Ok(_) => println!("  {} Fetched origin/{}", "✓".green(), base_branch),
+        match git::run(&["fetch", &git::upstream_remote(), base_branch]) {
+            Ok(_) => println!("  {} Fetched {}", "✓".green(), remote_base),
             Err(e) => println!("  {} Failed to fetch: {}", "⚠".yellow(), e),
         }
+
+        if Storage::current() == Storage::Refs {
+            match storage::pull() {
+                Ok(PullOutcome::FastForwarded) => {
+                    println!("  {} Fetched shared stack metadata", "✓".green())
+                }
+                Ok(PullOutcome::Merged) => {
+                    println!("  {} Merged teammates' stack changes", "✓".green())
+                }
+                Ok(_) => {}
+                Err(e) => println!(
+                    "  {} Failed to fetch shared stack metadata: {}",
+                    "⚠".yellow(),
+                    e
+                ),
+            }
+        }
     }
 
     // Check if local main is up to date
     let local_main = git::run(&["rev-parse", base_branch]).unwrap_or_default();
-    let remote_main = git::run(&["rev-parse", &format!("origin/{}", base_branch)]).unwrap_or_default();
+    let remote_main = git::run(&["rev-parse", remote_base]).unwrap_or_default();
 
     if local_main != remote_main && !local_main.is_empty() && !remote_main.is_empty() {
-        println!("  {} Local {} is behind origin/{}", "⚠".yellow(), base_branch, base_branch);
+        println!(
+            "  {} Local {} is behind {}",
+            "⚠".yellow(),
+            base_branch,
+            remote_base
+        );
         if !dry_run {
             let current = git::current_branch()?;
             if current != base_branch {
-                let _ = git::run(&["branch", "-f", base_branch, &format!("origin/{}", base_branch)]);
+                let _ = git::run(&["branch", "-f", base_branch, remote_base]);
                 println!("  {} Updated local {}", "✓".green(), base_branch);
             }
         }
@@ -39,30 +176,20 @@ pub fn run(dry_run: bool, _wait_ci: bool) -> Result<()> {
         println!("  {} Local {} is up to date", "✓".green(), base_branch);
     }
     println!();
+    Ok(())
+}
 
-    // Step 2: Auto-discover branch chain
-    println!("{}", "Discovering branch chain...".cyan());
-    let config = StackConfig::discover(base_branch).context("Failed to discover branches")?;
-
-    if config.branches.is_empty() {
-        println!("  {} No branches found", "⚠".yellow());
-        return Ok(());
-    }
-
-    println!("  {} (base)", base_branch.green());
-    for (i, branch) in config.branches.iter().enumerate() {
-        let is_last = i == config.branches.len() - 1;
-        if is_last {
-            println!("    └─ {}  ← current", branch.name.cyan().bold());
-        } else {
-            println!("    └─ {}", branch.name);
-        }
+/// Whichever of the local and remote base is further ahead
+fn newest_base<'a>(base_branch: &'a str, remote_base: &'a str) -> &'a str {
+    if git::rev_parse(remote_base).is_some() && !git::is_ancestor(remote_base, base_branch) {
+        remote_base
+    } else {
+        base_branch
     }
-    println!();
-
-    // Step 3: Sync PR bases
-    println!("{}", "Syncing PR targets...".cyan());
+}
 
+/// Point every PR at its branch's parent
+fn sync_pr_bases(config: &StackConfig, dry_run: bool) -> Result<()> {
     for branch_info in &config.branches {
         let pr = github::get_pr(&branch_info.name)?;
 
@@ -106,18 +233,441 @@ pub fn run(dry_run: bool, _wait_ci: bool) -> Result<()> {
             println!("  {} {} - no PR", "○".dimmed(), branch_info.name);
         }
     }
+    Ok(())
+}
+
+/// A stack branch whose changes are already in the base
+struct Merged {
+    name: String,
+    /// How that was found out
+    how: String,
+}
+
+/// Find branches whose PR was merged, or whose commits show up in `base`
+/// after a merge, rebase or squash
+fn find_merged(branches: &[BranchInfo], base: &str) -> Vec<Merged> {
+    branches
+        .iter()
+        .filter_map(|branch| {
+            let pr = branch
+                .pr_number
+                .and_then(|number| github::get_pr_by_number(number).ok());
+            if let Some(pr) = pr.filter(|pr| pr.state == "MERGED") {
+                return Some(Merged {
+                    name: branch.name.clone(),
+                    how: format!("PR #{} merged", pr.number),
+                });
+            }
+            git::is_merged_into(&branch.name, &branch.parent, base).then(|| Merged {
+                name: branch.name.clone(),
+                how: format!("changes found in {}", base),
+            })
+        })
+        .collect()
+}
+
+/// Drop merged branches from the stack and move the commits of the branches
+/// above them onto their new parents
+fn collapse(config: &mut StackConfig, merged: &[Merged]) -> Result<()> {
+    // Tips and parents from before the rebase: each branch only takes along
+    // the commits after its old parent's tip
+    let old_tips: HashMap<String, String> = config
+        .branches
+        .iter()
+        .filter_map(|b| Some((b.name.clone(), git::rev_parse(&b.name)?)))
+        .collect();
+    let old_parents: HashMap<String, String> = config
+        .branches
+        .iter()
+        .map(|b| (b.name.clone(), b.parent.clone()))
+        .collect();
+
+    for branch in merged {
+        config.remove_branch(&branch.name);
+    }
+
+    let mut rebased: HashSet<String> = HashSet::new();
+    for branch in &config.branches {
+        let old_parent = &old_parents[&branch.name];
+        if *old_parent == branch.parent && !rebased.contains(old_parent) {
+            continue;
+        }
+        let Some(old_base) = old_tips.get(old_parent) else {
+            continue;
+        };
+
+        print!(
+            "  Rebasing {} onto {} ... ",
+            branch.name,
+            branch.parent.green()
+        );
+        if let Err(e) = git::rebase_onto(&branch.parent, old_base, &branch.name) {
+            println!("{}", "✗".red());
+            let _ = git::run(&["rebase", "--abort"]);
+            return Err(e).with_context(|| {
+                format!(
+                    "Rebasing {} onto {} conflicted. Run `git rebase --onto {} {} {}`, resolve the conflicts, then run `gh flow sync` again",
+                    branch.name,
+                    branch.parent,
+                    branch.parent,
+                    &old_base[..7.min(old_base.len())],
+                    branch.name
+                )
+            });
+        }
+        println!("{}", "✓".green());
+        rebased.insert(branch.name.clone());
+    }
+
+    if !rebased.is_empty() {
+        println!("  Run `gh flow push --force` to update the rebased branches on GitHub");
+    }
+    Ok(())
+}
+
+/// Ask whether to delete the merged branches locally and on the remote.
+/// The branch sync started on is kept, since it is checked out again afterwards.
+fn offer_delete(merged: &[Merged], original_branch: &str) {
+    let (kept, deletable): (Vec<&Merged>, Vec<&Merged>) = merged
+        .iter()
+        .partition(|branch| branch.name == original_branch);
+    for branch in kept {
+        println!(
+            "  {} {} is merged but checked out; delete it after switching branches",
+            "○".dimmed(),
+            branch.name
+        );
+    }
+    if deletable.is_empty() {
+        return;
+    }
+
+    let names: Vec<&str> = deletable
+        .iter()
+        .map(|branch| branch.name.as_str())
+        .collect();
+    println!();
+    // Not a terminal or cancelled: keep the branches
+    let delete = Confirm::new()
+        .with_prompt(format!("Delete merged branches {}?", names.join(", ")))
+        .default(true)
+        .interact()
+        .unwrap_or(false);
+    if !delete {
+        println!("  Kept {}", names.join(", "));
+        return;
+    }
+
+    for name in names {
+        match git::delete_branch(name) {
+            Ok(()) => println!("  {} Deleted {}", "✓".green(), name),
+            Err(e) => println!("  {} Could not delete {}: {}", "✗".red(), name, e),
+        }
+        if git::remote_tip(name).is_none() {
+            continue;
+        }
+        match git::delete_remote_branch(name) {
+            Ok(()) => println!("  {} Deleted {}/{}", "✓".green(), git::push_remote(), name),
+            Err(e) => println!(
+                "  {} Could not delete {}/{}: {}",
+                "✗".red(),
+                git::push_remote(),
+                name,
+                e
+            ),
+        }
+    }
+}
+
+/// How one stack would change
+struct Restack {
+    merged: Vec<Merged>,
+    /// Rebased branches with their old and new tips
+    moved: Vec<(String, String, String)>,
+}
+
+/// Work out the new tips of a stack's branches after dropping merged ones and
+/// rebasing the rest onto their parents. Rebases happen in `scratch`, and no
+/// branch is updated yet; without one, only what would move is found.
+fn restack(
+    stack: &[BranchInfo],
+    base_branch: &str,
+    landed_in: &str,
+    scratch: Option<&Scratch>,
+) -> Result<Restack> {
+    let mut old_tips: HashMap<String, String> = HashMap::new();
+    for branch in stack {
+        let tip = git::rev_parse(&branch.name)
+            .with_context(|| format!("{} no longer exists; run `gh flow prune`", branch.name))?;
+        old_tips.insert(branch.name.clone(), tip);
+    }
+    let old_parents: HashMap<String, String> = stack
+        .iter()
+        .map(|b| (b.name.clone(), b.parent.clone()))
+        .collect();
+
+    let merged = find_merged(stack, landed_in);
+    let mut view = StackConfig {
+        base_branch: base_branch.to_string(),
+        branches: stack.to_vec(),
+        ..Default::default()
+    };
+    for branch in &merged {
+        view.remove_branch(&branch.name);
+    }
+
+    let mut new_tips: HashMap<String, String> = HashMap::new();
+    let mut moved = Vec::new();
+    for branch in &view.branches {
+        let tip = &old_tips[&branch.name];
+        let old_parent = &old_parents[&branch.name];
+        let onto = if branch.parent == base_branch {
+            git::rev_parse(landed_in).unwrap_or_else(|| landed_in.to_string())
+        } else {
+            new_tips
+                .get(&branch.parent)
+                .or_else(|| old_tips.get(&branch.parent))
+                .cloned()
+                .unwrap_or_else(|| branch.parent.clone())
+        };
+
+        // Below a dropped or rebased parent, only the branch's own commits move
+        let fork_point = if *old_parent != branch.parent || new_tips.contains_key(old_parent) {
+            old_tips[old_parent].clone()
+        } else if git::is_ancestor(&onto, tip) {
+            continue;
+        } else {
+            git::run(&["merge-base", &onto, tip])?
+        };
+
+        let new_tip = match scratch {
+            Some(scratch) => scratch.rebase(tip, &fork_point, &onto).with_context(|| {
+                format!("rebasing {} onto {} conflicts", branch.name, branch.parent)
+            })?,
+            None => tip.clone(),
+        };
+        new_tips.insert(branch.name.clone(), new_tip.clone());
+        moved.push((branch.name.clone(), tip.clone(), new_tip));
+    }
+
+    Ok(Restack { merged, moved })
+}
+
+/// Point the rebased branches at their new tips. The checked-out branch is
+/// moved with `reset --keep`, which keeps uncommitted changes or refuses.
+fn apply(
+    moved: &[(String, String, String)],
+    current: &str,
+    elsewhere: &HashMap<String, PathBuf>,
+) -> Result<()> {
+    for (name, _, _) in moved {
+        if let Some(path) = elsewhere.get(name) {
+            anyhow::bail!("{} is checked out in {}", name, path.display());
+        }
+    }
+    if let Some((_, _, new)) = moved.iter().find(|(name, _, _)| name == current) {
+        git::run(&["reset", "--keep", "--quiet", new])
+            .with_context(|| format!("uncommitted changes on {} are in the way", current))?;
+    }
+    for (name, old, new) in moved.iter().filter(|(name, _, _)| name != current) {
+        git::run(&["update-ref", &format!("refs/heads/{}", name), new, old])?;
+    }
+    Ok(())
+}
+
+/// Restack and retarget every tracked stack from one fetch, without
+/// switching branches
+fn sync_all(dry_run: bool) -> Result<()> {
+    if dry_run {
+        println!("{}", "[DRY RUN] Sync operations:".yellow().bold());
+    } else {
+        println!("{}", "Synchronizing every stack...".green().bold());
+    }
+    println!();
+
+    let base_branch = StackConfig::load()
+        .context("Failed to load configuration")?
+        .base()?;
+    let remote_base = format!("{}/{}", git::upstream_remote(), base_branch);
+    fetch_base(dry_run, &base_branch, &remote_base)?;
+
+    // Loaded after fetching, which may have merged teammates' changes
+    let mut config = StackConfig::load().context("Failed to load configuration")?;
+    if config.branches.is_empty() {
+        println!(
+            "{}",
+            "No branches in stack. Run `gh flow init` first.".yellow()
+        );
+        return Ok(());
+    }
+
+    let landed_in = newest_base(&base_branch, &remote_base);
+    let current = git::current_branch().unwrap_or_default();
+    let elsewhere = worktree::checked_out_elsewhere()?;
+    let scratch = if dry_run {
+        None
+    } else {
+        Some(Scratch::create()?)
+    };
+
+    println!("{}", "Restacking...".cyan());
+    let mut attention: Vec<(String, String)> = Vec::new();
+    let mut merged_any = false;
+    let mut moved_any = false;
+    for stack in config.stacks() {
+        let label = stack
+            .iter()
+            .map(|b| b.name.as_str())
+            .collect::<Vec<_>>()
+            .join(" → ");
+        let result =
+            restack(&stack, &base_branch, landed_in, scratch.as_ref()).and_then(|restack| {
+                if !dry_run {
+                    apply(&restack.moved, &current, &elsewhere)?;
+                }
+                Ok(restack)
+            });
+
+        let restack = match result {
+            Ok(restack) => restack,
+            Err(e) => {
+                println!("  {} {}: {}", "✗".red(), label, e);
+                attention.push((stack[0].name.clone(), e.to_string()));
+                continue;
+            }
+        };
+
+        if restack.merged.is_empty() && restack.moved.is_empty() {
+            println!("  {} {} is up to date", "✓".green(), label);
+            continue;
+        }
+        let verb = if dry_run {
+            "Would restack"
+        } else {
+            "Restacked"
+        };
+        println!("  {} {} {}", "↻".yellow(), verb, label);
+        for branch in &restack.merged {
+            println!(
+                "    {} {} ({}), dropped from the stack",
+                "✓".green(),
+                branch.name.cyan(),
+                branch.how
+            );
+            config.remove_branch(&branch.name);
+        }
+        for (name, _, _) in &restack.moved {
+            let parent = config
+                .get_branch(name)
+                .map(|b| b.parent.clone())
+                .unwrap_or_default();
+            println!("    {} {} onto {}", "✓".green(), name, parent.green());
+        }
+        merged_any |= !restack.merged.is_empty();
+        moved_any |= !restack.moved.is_empty();
+    }
+    drop(scratch);
+    println!();
+
+    println!("{}", "Syncing PR targets...".cyan());
+    sync_pr_bases(&config, dry_run)?;
 
-    // Save config
     if !dry_run {
+        for root in config.roots() {
+            ready::apply_draft_policy(&config.stack_for(&root.name)?)?;
+        }
         config.save().context("Failed to save config")?;
+        push::share_stack();
     }
 
     println!();
+    if !attention.is_empty() {
+        println!(
+            "{}",
+            match attention.len() {
+                1 => "⚠ 1 stack needs attention:".to_string(),
+                count => format!("⚠ {} stacks need attention:", count),
+            }
+            .yellow()
+            .bold()
+        );
+        for (bottom, reason) in &attention {
+            println!("  {} {}: {}", "✗".red(), bottom.cyan(), reason);
+        }
+        println!("  Check out a branch of each and run `gh flow sync` to resolve it there");
+        println!();
+    }
+    if !dry_run && moved_any {
+        println!("Run `gh flow push --force` on each rebased stack to update GitHub");
+    }
+    if !dry_run && merged_any {
+        println!("Run `gh flow prune` to delete the merged branches");
+    }
+
     if dry_run {
         println!("{}", "✓ Dry run complete".yellow());
-    } else {
-        println!("{}", "✓ Stack synchronized".green().bold());
+    } else if attention.is_empty() {
+        println!("{}", "✓ Every stack synchronized".green().bold());
     }
-
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn two_stacks() -> StackConfig {
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        for (name, parent) in [("a1", "main"), ("b1", "main"), ("a2", "a1"), ("b2", "b1")] {
+            config.add_branch(name.to_string(), parent.to_string());
+        }
+        config
+    }
+
+    fn names(stack: &[BranchInfo]) -> Vec<&str> {
+        stack.iter().map(|b| b.name.as_str()).collect()
+    }
+
+    #[test]
+    fn restacks_every_stack_onto_the_new_base() {
+        let repo = Fixture::new();
+        for stack in ["a", "b"] {
+            repo.git(&["checkout", "--quiet", "main"]);
+            repo.branch(&format!("{}1", stack));
+            repo.commit_file(&format!("{}1.txt", stack), "1\n");
+            repo.branch(&format!("{}2", stack));
+            repo.commit_file(&format!("{}2.txt", stack), "2\n");
+        }
+        repo.git(&["checkout", "--quiet", "main"]);
+        let new_base = repo.commit_file("main.txt", "moved\n");
+        repo.git(&["checkout", "--quiet", "a2"]);
+
+        let config = two_stacks();
+        let scratch = Scratch::create().unwrap();
+        for stack in config.stacks() {
+            let restack = restack(&stack, "main", "main", Some(&scratch)).unwrap();
+            let moved: Vec<&str> = restack
+                .moved
+                .iter()
+                .map(|(name, _, _)| name.as_str())
+                .collect();
+            assert_eq!(moved, names(&stack));
+            apply(&restack.moved, "a2", &HashMap::new()).unwrap();
+        }
+        drop(scratch);
+
+        assert_eq!(git::current_branch().unwrap(), "a2");
+        for branch in ["a1", "a2", "b1", "b2"] {
+            assert!(
+                git::is_ancestor(&new_base, branch),
+                "{} was not restacked",
+                branch
+            );
+        }
+        assert!(git::is_ancestor("a1", "a2") && git::is_ancestor("b1", "b2"));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    stack::{self, StackConfig},
+    template,
+};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::BTreeSet;
+
+use super::pr;
+
+const KNOWN_VARIABLES: &str = "stack, branch, title, parent, base, pr_number, position, total, commits, commit_messages, first, last, branches";
+
+pub fn check(show: bool) -> Result<()> {
+    println!("{}", "Checking PR template...".green().bold());
+    println!();
+
+    match stack::get_pr_template_path()? {
+        Some(path) => println!("Template: {}", path.display().to_string().cyan()),
+        None => println!("Template: {}", "built-in default".cyan()),
+    }
+    println!();
+
+    let tmpl = stack::load_pr_template().unwrap_or_else(|| pr::DEFAULT_PR_TEMPLATE.to_string());
+
+    let mut config = StackConfig::load().context("Failed to load configuration")?;
+    if config.branches.is_empty() {
+        let mut discovered =
+            StackConfig::discover(&config.base()?).context("Failed to discover branches")?;
+        discovered.keep_saved_state(&config);
+        config = discovered;
+    }
+
+    if config.branches.is_empty() {
+        println!(
+            "{}",
+            "No branches in stack. Run `gh flow init` first.".yellow()
+        );
+        return Ok(());
+    }
+
+    let mut unknown: BTreeSet<String> = BTreeSet::new();
+
+    for branch_info in &config.branches {
+        let title = pr::generate_title(&config, &branch_info.name);
+
+        let ctx = pr::template_context(&config, &branch_info.name, &title, &pr::PrStatuses::new());
+        let rendered = template::render(&tmpl, &ctx).context("Template syntax error")?;
+
+        if rendered.unknown.is_empty() {
+            println!("  {} {}", "✓".green(), branch_info.name.cyan());
+        } else {
+            println!(
+                "  {} {} - unknown variables: {}",
+                "✗".red(),
+                branch_info.name.cyan(),
+                rendered.unknown.join(", ").red()
+            );
+            unknown.extend(rendered.unknown);
+        }
+
+        if show {
+            println!();
+            for line in rendered.output.trim().lines() {
+                println!("    {}", line.dimmed());
+            }
+            println!();
+        }
+    }
+
+    println!();
+    if !unknown.is_empty() {
+        anyhow::bail!(
+            "Template references unknown variables: {}\n\nAvailable variables: {}",
+            unknown.into_iter().collect::<Vec<_>>().join(", "),
+            KNOWN_VARIABLES
+        );
+    }
+
+    println!(
+        "{} Template renders for all {} branches",
+        "✓".green(),
+        config.branches.len()
+    );
+
+    Ok(())
+}
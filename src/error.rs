@@ -0,0 +1,210 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Failures with a known cause, each with its own exit code and a suggestion
+/// on how to get past it. Anything else exits with code 1.
+#[derive(Debug)]
+pub enum FlowError {
+    NotARepo,
+    GitMissing,
+    GhMissing,
+    /// gh has no valid token for the host
+    Unauthenticated,
+    /// Uncommitted changes to tracked files
+    DirtyWorktree,
+    DetachedHead,
+    RebaseInProgress,
+    BranchMissing(String),
+    RateLimited,
+    /// Another gh-flow operation holds the stack lock
+    Locked {
+        pid: u32,
+        command: String,
+        seconds: u64,
+        path: PathBuf,
+    },
+    /// A git command failed for another reason; `command` is the subcommand
+    Git {
+        command: String,
+        stderr: String,
+    },
+    /// A gh command failed for another reason; `command` is the subcommand
+    Gh {
+        command: String,
+        stderr: String,
+    },
+}
+
+impl FlowError {
+    /// Classify a failed git command
+    pub fn from_git(args: &[&str], stderr: &str) -> Self {
+        if stderr.contains("not a git repository") {
+            return FlowError::NotARepo;
+        }
+        if stderr.contains("local changes") && stderr.contains("would be overwritten") {
+            return FlowError::DirtyWorktree;
+        }
+        if stderr.contains("unknown revision") || stderr.contains("did not match any") {
+            // git quotes the name it couldn't resolve
+            let name = stderr
+                .split('\'')
+                .nth(1)
+                .or_else(|| args.last().copied())
+                .unwrap_or_default();
+            return FlowError::BranchMissing(name.to_string());
+        }
+        FlowError::Git {
+            command: args.first().copied().unwrap_or_default().to_string(),
+            stderr: stderr.trim().to_string(),
+        }
+    }
+
+    /// Classify a failed gh command
+    pub fn from_gh(args: &[&str], stderr: &str) -> Self {
+        let lower = stderr.to_lowercase();
+        if lower.contains("rate limit") {
+            return FlowError::RateLimited;
+        }
+        if lower.contains("gh auth login")
+            || lower.contains("http 401")
+            || lower.contains("bad credentials")
+        {
+            return FlowError::Unauthenticated;
+        }
+        FlowError::Gh {
+            command: args.iter().take(2).copied().collect::<Vec<_>>().join(" "),
+            stderr: stderr.trim().to_string(),
+        }
+    }
+
+    /// Process exit code
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            FlowError::NotARepo => 10,
+            FlowError::GitMissing => 11,
+            FlowError::GhMissing => 12,
+            FlowError::Unauthenticated => 13,
+            FlowError::DirtyWorktree => 14,
+            FlowError::DetachedHead => 15,
+            FlowError::RebaseInProgress => 16,
+            FlowError::BranchMissing(_) => 17,
+            FlowError::RateLimited => 18,
+            FlowError::Locked { .. } => 19,
+            FlowError::Git { .. } => 20,
+            FlowError::Gh { .. } => 21,
+        }
+    }
+
+    /// What to do about it
+    pub fn suggestion(&self) -> Option<String> {
+        Some(match self {
+            FlowError::NotARepo => "Run this command inside a git repository".to_string(),
+            FlowError::GitMissing => "Install git from https://git-scm.com".to_string(),
+            FlowError::GhMissing => "Install the GitHub CLI from https://cli.github.com".to_string(),
+            FlowError::Unauthenticated => "Run: gh auth login".to_string(),
+            FlowError::DirtyWorktree => {
+                "Commit or stash your changes, or pass --autostash to have gh-flow stash and restore them".to_string()
+            }
+            FlowError::DetachedHead => "Check out a branch of the stack first".to_string(),
+            FlowError::RebaseInProgress => {
+                "Finish it with `git rebase --continue`, or give up with `git rebase --abort`".to_string()
+            }
+            FlowError::BranchMissing(_) => {
+                "Check the branch name, or run `gh flow doctor` if it was part of the stack".to_string()
+            }
+            FlowError::RateLimited => "Wait for the rate limit to reset, then run the command again".to_string(),
+            FlowError::Locked { path, .. } => format!(
+                "Wait for it to finish, or rerun with --wait. If no gh-flow is running, delete {}",
+                path.display()
+            ),
+            FlowError::Git { .. } | FlowError::Gh { .. } => return None,
+        })
+    }
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowError::NotARepo => write!(f, "Not a git repository"),
+            FlowError::GitMissing => write!(f, "git is not installed"),
+            FlowError::GhMissing => write!(f, "GitHub CLI (gh) is not installed"),
+            FlowError::Unauthenticated => write!(f, "GitHub CLI not authenticated"),
+            FlowError::DirtyWorktree => write!(f, "You have uncommitted changes"),
+            FlowError::DetachedHead => write!(f, "HEAD is detached"),
+            FlowError::RebaseInProgress => write!(f, "A rebase is in progress"),
+            FlowError::BranchMissing(branch) => write!(f, "Branch '{}' does not exist", branch),
+            FlowError::RateLimited => write!(f, "GitHub API rate limit exceeded"),
+            FlowError::Locked {
+                pid,
+                command,
+                seconds,
+                ..
+            } => write!(
+                f,
+                "Another gh-flow operation is running: `{}` (pid {}, started {}s ago)",
+                command, pid, seconds
+            ),
+            FlowError::Git { command, stderr } => write!(f, "git {} failed: {}", command, stderr),
+            FlowError::Gh { command, stderr } => write!(f, "gh {} failed: {}", command, stderr),
+        }
+    }
+}
+
+impl std::error::Error for FlowError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_missing_revisions() {
+        let error = FlowError::from_git(
+            &["log", "nope..HEAD"],
+            "fatal: ambiguous argument 'nope..HEAD': unknown revision or path not in the working tree.",
+        );
+        assert!(matches!(error, FlowError::BranchMissing(name) if name == "nope..HEAD"));
+
+        let error = FlowError::from_git(
+            &["checkout", "feature"],
+            "error: pathspec 'feature' did not match any file(s) known to git",
+        );
+        assert!(matches!(error, FlowError::BranchMissing(name) if name == "feature"));
+    }
+
+    #[test]
+    fn classifies_overwritten_local_changes() {
+        let stderr = "error: Your local changes to the following files would be overwritten by checkout:\n\ta.txt\n\
+                      Please commit your changes or stash them before you switch branches.\nAborting";
+        let error = FlowError::from_git(&["checkout", "b"], stderr);
+        assert!(matches!(error, FlowError::DirtyWorktree));
+        assert_eq!(error.exit_code(), 14);
+    }
+
+    #[test]
+    fn other_git_failures_keep_the_command_and_stderr() {
+        let error =
+            FlowError::from_git(&["push", "origin"], "  ! [rejected] a -> a (stale info)\n");
+        assert!(
+            matches!(&error, FlowError::Git { command, stderr } if command == "push" && stderr == "! [rejected] a -> a (stale info)")
+        );
+        assert!(matches!(
+            FlowError::from_git(&["status"], "fatal: not a git repository"),
+            FlowError::NotARepo
+        ));
+    }
+
+    #[test]
+    fn classifies_gh_failures() {
+        assert!(matches!(
+            FlowError::from_gh(&["api"], "API rate limit exceeded"),
+            FlowError::RateLimited
+        ));
+        assert!(matches!(
+            FlowError::from_gh(&["pr", "list"], "HTTP 401: Bad credentials"),
+            FlowError::Unauthenticated
+        ));
+        assert!(
+            matches!(FlowError::from_gh(&["pr", "view", "1"], "no pull requests found"), FlowError::Gh { command, .. } if command == "pr view")
+        );
+    }
+}
@@ -1,21 +1,89 @@
+use crate::error::FlowError;
 use anyhow::{Context, Result};
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+fn spawn_failed(e: io::Error) -> anyhow::Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        FlowError::GitMissing.into()
+    } else {
+        anyhow::Error::new(e).context("Failed to execute git command")
+    }
+}
 
 /// Run a git command and return output
 pub fn run(args: &[&str]) -> Result<String> {
+    run_in(Path::new("."), args)
+}
+
+/// Run a git command in another working tree and return output
+pub fn run_in(dir: &Path, args: &[&str]) -> Result<String> {
     let output = Command::new("git")
+        .current_dir(dir)
         .args(args)
         .output()
-        .context("Failed to execute git command")?;
+        .map_err(spawn_failed)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(FlowError::from_git(args, &stderr).into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Run a git command with `input` on stdin and return output
+pub fn run_with_input(args: &[&str], input: &str) -> Result<String> {
+    let mut child = Command::new("git")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_failed)?;
+
+    child
+        .stdin
+        .take()
+        .context("Failed to open git stdin")?
+        .write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("Git command failed: {}", stderr);
+        return Err(FlowError::from_git(args, &stderr).into());
     }
 
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
 
+/// Read a git config value, or None if it isn't set
+pub fn config_get(key: &str) -> Option<String> {
+    run(&["config", "--get", key])
+        .ok()
+        .filter(|v| !v.is_empty())
+}
+
+/// Remote that stack branches are pushed to (`gh-flow.remote`, default `origin`)
+pub fn push_remote() -> String {
+    static REMOTE: OnceLock<String> = OnceLock::new();
+    REMOTE
+        .get_or_init(|| config_get("gh-flow.remote").unwrap_or_else(|| "origin".to_string()))
+        .clone()
+}
+
+/// Remote of the repository PRs are opened against (`gh-flow.upstream`,
+/// default: the push remote). Differs from the push remote when working on a fork.
+pub fn upstream_remote() -> String {
+    static REMOTE: OnceLock<String> = OnceLock::new();
+    REMOTE
+        .get_or_init(|| config_get("gh-flow.upstream").unwrap_or_else(push_remote))
+        .clone()
+}
+
 /// Get current branch name
 pub fn current_branch() -> Result<String> {
     run(&["branch", "--show-current"])
@@ -33,18 +101,210 @@ pub fn branch_exists(branch: &str) -> Result<bool> {
     Ok(branches.contains(&branch.to_string()))
 }
 
-/// Rebase current branch onto target
-pub fn rebase(target: &str) -> Result<()> {
-    run(&["rebase", target])?;
+/// Get commit subjects in `range`, oldest first
+pub fn commit_subjects(range: &str) -> Result<Vec<String>> {
+    let output = run(&["log", "--reverse", "--format=%s", range])?;
+    Ok(output.lines().map(String::from).collect())
+}
+
+/// Subject and body of a commit
+pub struct CommitMessage {
+    pub sha: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Get full commit messages in `range`, oldest first
+pub fn commit_messages(range: &str) -> Result<Vec<CommitMessage>> {
+    // Unit/record separators keep multi-line bodies intact
+    let output = run(&["log", "--reverse", "--format=%H%x1f%s%x1f%b%x1e", range])?;
+    Ok(output
+        .split('\x1e')
+        .map(str::trim)
+        .filter(|record| !record.is_empty())
+        .map(|record| {
+            let mut fields = record.splitn(3, '\x1f');
+            CommitMessage {
+                sha: fields.next().unwrap_or_default().trim().to_string(),
+                subject: fields.next().unwrap_or_default().trim().to_string(),
+                body: fields.next().unwrap_or_default().trim().to_string(),
+            }
+        })
+        .collect())
+}
+
+/// Get files changed on `branch` since it forked from `parent`
+pub fn changed_files(parent: &str, branch: &str) -> Result<Vec<String>> {
+    let output = run(&["diff", "--name-only", &format!("{}...{}", parent, branch)])?;
+    Ok(output.lines().map(String::from).collect())
+}
+
+/// Resolve a revision to a commit hash, or None if it doesn't exist
+pub fn rev_parse(rev: &str) -> Option<String> {
+    run(&[
+        "rev-parse",
+        "--verify",
+        "--quiet",
+        &format!("{}^{{commit}}", rev),
+    ])
+    .ok()
+}
+
+/// Get the commit the remote-tracking branch of the push remote points to
+pub fn remote_tip(branch: &str) -> Option<String> {
+    rev_parse(&format!("refs/remotes/{}/{}", push_remote(), branch))
+}
+
+/// Rebase `branch` onto `onto`
+pub fn rebase_branch(onto: &str, branch: &str) -> Result<()> {
+    run(&["rebase", onto, branch])?;
     Ok(())
 }
 
-/// Push branch to remote
-pub fn push(branch: &str, force: bool) -> Result<()> {
-    let mut args = vec!["push", "origin", branch];
-    if force {
-        args.push("--force-with-lease");
+/// Move the commits of `branch` after `old_base` onto `new_base`
+pub fn rebase_onto(new_base: &str, old_base: &str, branch: &str) -> Result<()> {
+    run(&["rebase", "--onto", new_base, old_base, branch])?;
+    Ok(())
+}
+
+/// A branch to push and what the remote is expected to point at
+pub struct PushSpec {
+    pub branch: String,
+    pub force: bool,
+    /// Remote commit to lease against; empty means the remote branch must not exist.
+    /// None falls back to the remote-tracking ref.
+    pub expected: Option<String>,
+}
+
+impl PushSpec {
+    fn lease_arg(&self) -> Option<String> {
+        if !self.force {
+            return None;
+        }
+        Some(match &self.expected {
+            Some(sha) => format!("--force-with-lease={}:{}", self.branch, sha),
+            None => format!("--force-with-lease={}", self.branch),
+        })
     }
+}
+
+/// Push branch to remote
+pub fn push(spec: &PushSpec) -> Result<()> {
+    let mut args = vec!["push".to_string(), push_remote()];
+    args.extend(spec.lease_arg());
+    args.push(spec.branch.clone());
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run(&args)?;
+    Ok(())
+}
+
+/// Push several branches in a single all-or-nothing `git push --atomic`
+pub fn push_atomic(specs: &[PushSpec]) -> Result<()> {
+    let mut args = vec!["push".to_string(), "--atomic".to_string(), push_remote()];
+    args.extend(specs.iter().filter_map(PushSpec::lease_arg));
+    args.extend(specs.iter().map(|spec| spec.branch.clone()));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
     run(&args)?;
     Ok(())
 }
+
+/// Delete a local branch, merged or not
+pub fn delete_branch(branch: &str) -> Result<()> {
+    run(&["branch", "-D", branch])?;
+    Ok(())
+}
+
+/// Delete a branch on the push remote
+pub fn delete_remote_branch(branch: &str) -> Result<()> {
+    run(&["push", "--quiet", &push_remote(), "--delete", branch])?;
+    Ok(())
+}
+
+/// Get the commits branches point to on the push remote, without fetching.
+/// Missing branches are left out.
+pub fn ls_remote(branches: &[String]) -> Result<HashMap<String, String>> {
+    let mut args = vec!["ls-remote".to_string(), push_remote()];
+    args.extend(branches.iter().map(|b| format!("refs/heads/{}", b)));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = run(&args)?;
+
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let (sha, refname) = line.split_once('\t')?;
+            let branch = refname.strip_prefix("refs/heads/")?;
+            Some((branch.to_string(), sha.to_string()))
+        })
+        .collect())
+}
+
+/// Check whether `ancestor` is reachable from `descendant`
+pub fn is_ancestor(ancestor: &str, descendant: &str) -> bool {
+    run(&["merge-base", "--is-ancestor", ancestor, descendant]).is_ok()
+}
+
+/// Patch ids of the non-merge commits in `range`
+fn patch_ids(range: &str) -> Result<HashSet<String>> {
+    let log = run(&["log", "-p", "--no-merges", "--no-color", range])?;
+    if log.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let ids = run_with_input(&["patch-id", "--stable"], &format!("{}\n", log))?;
+    Ok(ids
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect())
+}
+
+/// Patch id of the whole change from `from` to `to`, as if squashed into one commit
+fn squashed_patch_id(from: &str, to: &str) -> Result<Option<String>> {
+    let diff = run(&["diff", "--no-color", from, to])?;
+    if diff.is_empty() {
+        return Ok(None);
+    }
+    let id = run_with_input(&["patch-id", "--stable"], &format!("{}\n", diff))?;
+    Ok(id.split_whitespace().next().map(String::from))
+}
+
+/// Check whether the commits `branch` adds on top of `parent` have landed in
+/// `base`, whether merged, rebased commit by commit or squashed into one.
+/// A branch with no commits of its own never counts as merged.
+pub fn is_merged_into(branch: &str, parent: &str, base: &str) -> bool {
+    let own = format!("{}..{}", parent, branch);
+    if run(&["rev-list", "--count", &own]).map_or(true, |count| count == "0") {
+        return false;
+    }
+    if is_ancestor(branch, base) {
+        return true;
+    }
+    let Ok(merge_base) = run(&["merge-base", base, branch]) else {
+        return false;
+    };
+
+    // Rebase and squash merges recreate the branch's patches on the base
+    if let Ok(landed) = patch_ids(&format!("{}..{}", merge_base, base)) {
+        let fork_point =
+            run(&["merge-base", parent, branch]).unwrap_or_else(|_| parent.to_string());
+        if let Ok(Some(id)) = squashed_patch_id(&fork_point, branch) {
+            if landed.contains(&id) {
+                return true;
+            }
+        }
+        if let Ok(commits) = patch_ids(&own) {
+            if !commits.is_empty() && commits.is_subset(&landed) {
+                return true;
+            }
+        }
+    }
+
+    // Merged after conflicts were resolved, or amended on the way in: merging
+    // the branch into the base changes nothing
+    let (Ok(merged), Ok(base_tree)) = (
+        run(&["merge-tree", "--write-tree", base, branch]),
+        run(&["rev-parse", &format!("{}^{{tree}}", base)]),
+    ) else {
+        return false;
+    };
+    merged.lines().next() == Some(base_tree.as_str())
+}
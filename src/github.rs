@@ -1,6 +1,9 @@
+use crate::{error::FlowError, git, stack};
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use std::io;
+use std::process::{Command, Output};
+use std::sync::OnceLock;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PullRequest {
@@ -10,19 +13,195 @@ pub struct PullRequest {
     pub base_ref: String,
     #[serde(rename = "headRefName")]
     pub head_ref: String,
+    /// Commit the head branch pointed to last
+    #[serde(rename = "headRefOid", default)]
+    pub head_sha: String,
     pub state: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(rename = "headRepositoryOwner", default)]
+    pub head_owner: Option<RepoOwner>,
+    /// Opened from a fork
+    #[serde(rename = "isCrossRepository", default)]
+    pub cross_repository: bool,
+}
+
+/// Fields requested for [`PullRequest`]
+const PR_FIELDS: &str = "number,title,baseRefName,headRefName,headRefOid,state,url,headRepositoryOwner,isCrossRepository";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RepoOwner {
+    pub login: String,
+}
+
+/// Live state of a PR, used for the stack table in PR descriptions
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrDetails {
+    pub state: String,
+    #[serde(rename = "isDraft")]
+    pub is_draft: bool,
+    #[serde(rename = "reviewDecision", default)]
+    pub review_decision: Option<String>,
+    #[serde(rename = "statusCheckRollup", default)]
+    pub checks: Vec<StatusCheck>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct StatusCheck {
+    #[serde(default)]
+    pub conclusion: Option<String>,
+    /// Commit status contexts report `state` instead of `conclusion`
+    #[serde(default)]
+    pub state: Option<String>,
+}
+
+impl PrDetails {
+    /// Whether any CI check on the PR failed
+    pub fn ci_failing(&self) -> bool {
+        self.checks.iter().any(|check| {
+            let result = check
+                .conclusion
+                .as_deref()
+                .or(check.state.as_deref())
+                .unwrap_or("");
+            matches!(
+                result.to_uppercase().as_str(),
+                "FAILURE" | "ERROR" | "CANCELLED" | "TIMED_OUT" | "ACTION_REQUIRED"
+            )
+        })
+    }
+}
+
+/// Reviewers, labels, assignees and milestone of a PR
+#[derive(Debug, Default, Clone)]
+pub struct PrMetadata {
+    /// Users, or teams as `org/team`
+    pub reviewers: Vec<String>,
+    pub labels: Vec<String>,
+    pub assignees: Vec<String>,
+    pub milestone: Option<String>,
+}
+
+impl PrMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.reviewers.is_empty()
+            && self.labels.is_empty()
+            && self.assignees.is_empty()
+            && self.milestone.is_none()
+    }
+}
+
+/// Repository PRs are opened against (the upstream remote)
+fn upstream_repo() -> Option<&'static stack::RemoteRepo> {
+    static REPO: OnceLock<Option<stack::RemoteRepo>> = OnceLock::new();
+    REPO.get_or_init(|| stack::get_repo().ok()).as_ref()
+}
+
+/// Owner of the fork branches are pushed to, when it isn't the upstream repository
+fn fork_owner() -> Option<&'static str> {
+    static OWNER: OnceLock<Option<String>> = OnceLock::new();
+    OWNER
+        .get_or_init(|| {
+            let fork = stack::get_remote_repo(&git::push_remote()).ok()?;
+            if Some(&fork) == upstream_repo() {
+                return None;
+            }
+            Some(fork.owner)
+        })
+        .as_deref()
+}
+
+/// GitHub Enterprise Server host to point gh at. None for github.com (also
+/// behind an ssh host alias), or when `GH_HOST` is already set.
+fn enterprise_host() -> Option<&'static str> {
+    if std::env::var_os("GH_HOST").is_some() {
+        return None;
+    }
+    upstream_repo()
+        .filter(|repo| !repo.is_github_com())
+        .map(|repo| repo.host.as_str())
+}
+
+/// Host gh talks to: `GH_HOST`, or the upstream remote's host
+pub fn host() -> Option<String> {
+    std::env::var("GH_HOST")
+        .ok()
+        .or_else(|| upstream_repo().map(|repo| repo.host.clone()))
+}
+
+/// Owner whose branches the stack's PRs are opened from
+fn head_owner() -> Option<&'static str> {
+    fork_owner().or_else(|| upstream_repo().map(|repo| repo.owner.as_str()))
+}
+
+/// `--head` value for a branch: `owner:branch` when pushing to a fork
+pub fn head_ref(branch: &str) -> String {
+    match fork_owner() {
+        Some(owner) => format!("{}:{}", owner, branch),
+        None => branch.to_string(),
+    }
+}
+
+/// Whether a PR was opened from the repository the stack pushes to
+fn from_stack_repo(pr: &PullRequest) -> bool {
+    match (&pr.head_owner, head_owner()) {
+        (Some(owner), Some(expected)) => owner.login.eq_ignore_ascii_case(expected),
+        _ => true,
+    }
+}
+
+/// Build a gh command aimed at the upstream repository and its host, so PRs
+/// are found and opened there even when branches are pushed to a fork
+fn gh_command(args: &[&str]) -> Command {
+    let mut cmd = Command::new("gh");
+    let host = enterprise_host();
+    if let Some(host) = host {
+        cmd.env("GH_HOST", host);
+    }
+
+    match (args, upstream_repo()) {
+        (["pr", subcommand, rest @ ..], Some(repo)) => {
+            cmd.args(["pr", subcommand, "--repo", &repo.id()])
+                .args(rest);
+        }
+        (["api", rest @ ..], Some(repo)) => {
+            cmd.arg("api");
+            if let Some(host) = host {
+                cmd.args(["--hostname", host]);
+            }
+            cmd.args(rest.iter().map(|arg| {
+                if arg.starts_with("repos/") {
+                    arg.replace("{owner}/{repo}", &repo.id())
+                } else {
+                    arg.to_string()
+                }
+            }));
+        }
+        _ => {
+            cmd.args(args);
+        }
+    }
+    cmd
+}
+
+/// Run a gh command, failing with [`FlowError::GhMissing`] if gh isn't installed
+fn gh_output(args: &[&str]) -> Result<Output> {
+    gh_command(args).output().map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            FlowError::GhMissing.into()
+        } else {
+            anyhow::Error::new(e).context("Failed to execute gh command")
+        }
+    })
 }
 
 /// Run gh CLI command and return output
 fn run_gh(args: &[&str]) -> Result<String> {
-    let output = Command::new("gh")
-        .args(args)
-        .output()
-        .context("Failed to execute gh command. Is gh CLI installed?")?;
+    let output = gh_output(args)?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("gh command failed: {}", stderr);
+        return Err(FlowError::from_gh(args, &stderr).into());
     }
 
     Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
@@ -31,31 +210,74 @@ fn run_gh(args: &[&str]) -> Result<String> {
 /// Get PR for a branch
 pub fn get_pr(branch: &str) -> Result<Option<PullRequest>> {
     let output = run_gh(&[
-        "pr",
-        "list",
-        "--head",
-        branch,
-        "--json",
-        "number,title,baseRefName,headRefName,state",
-        "--limit",
-        "1",
+        "pr", "list", "--head", branch, "--json", PR_FIELDS, "--limit", "100",
     ])?;
 
     let prs: Vec<PullRequest> = serde_json::from_str(&output).context("Failed to parse PR JSON")?;
 
-    Ok(prs.into_iter().next())
+    // Forks can have branches with the same name
+    Ok(prs.into_iter().find(from_stack_repo))
+}
+
+/// Get a PR by number
+pub fn get_pr_by_number(pr_number: u32) -> Result<PullRequest> {
+    let output = run_gh(&["pr", "view", &pr_number.to_string(), "--json", PR_FIELDS])?;
+    serde_json::from_str(&output).context("Failed to parse PR JSON")
+}
+
+/// List open PRs targeting `base`
+pub fn list_prs_with_base(base: &str) -> Result<Vec<PullRequest>> {
+    let output = run_gh(&[
+        "pr", "list", "--base", base, "--json", PR_FIELDS, "--limit", "100",
+    ])?;
+
+    let mut prs: Vec<PullRequest> =
+        serde_json::from_str(&output).context("Failed to parse PR JSON")?;
+    prs.sort_by_key(|pr| pr.number);
+    Ok(prs)
+}
+
+/// List all open PRs of the repository in one request
+pub fn list_open_prs() -> Result<Vec<PullRequest>> {
+    let output = run_gh(&[
+        "pr", "list", "--state", "open", "--json", PR_FIELDS, "--limit", "1000",
+    ])?;
+
+    let prs: Vec<PullRequest> = serde_json::from_str(&output).context("Failed to parse PR JSON")?;
+    Ok(prs.into_iter().filter(from_stack_repo).collect())
+}
+
+/// List the most recent PRs of the repository in any state, newest first, in one request
+pub fn list_recent_prs() -> Result<Vec<PullRequest>> {
+    let output = run_gh(&[
+        "pr", "list", "--state", "all", "--json", PR_FIELDS, "--limit", "1000",
+    ])?;
+
+    let prs: Vec<PullRequest> = serde_json::from_str(&output).context("Failed to parse PR JSON")?;
+    Ok(prs.into_iter().filter(from_stack_repo).collect())
 }
 
 /// Create a new PR
-pub fn create_pr(head: &str, base: &str, title: &str, body: &str, draft: bool) -> Result<u32> {
+pub fn create_pr(
+    head: &str,
+    base: &str,
+    title: &str,
+    body: &str,
+    draft: bool,
+    metadata: &PrMetadata,
+) -> Result<u32> {
+    let head_ref = head_ref(head);
     let mut args = vec![
-        "pr", "create", "--head", head, "--base", base, "--title", title, "--body", body,
+        "pr", "create", "--head", &head_ref, "--base", base, "--title", title, "--body", body,
     ];
 
     if draft {
         args.push("--draft");
     }
 
+    let metadata_args = metadata_args(metadata, "--");
+    args.extend(metadata_args.iter().map(String::as_str));
+
     let output = run_gh(&args)?;
 
     // Parse PR URL to get number
@@ -75,37 +297,262 @@ pub fn create_pr(head: &str, base: &str, title: &str, body: &str, draft: bool) -
     anyhow::bail!("Failed to parse PR number from output: {}", output)
 }
 
+/// Get state, draft flag, review decision and CI checks of a PR
+pub fn get_pr_details(pr_number: u32) -> Result<PrDetails> {
+    let output = run_gh(&[
+        "pr",
+        "view",
+        &pr_number.to_string(),
+        "--json",
+        "state,isDraft,reviewDecision,statusCheckRollup",
+    ])?;
+    serde_json::from_str(&output).context("Failed to parse PR JSON")
+}
+
+/// Find the id of the first PR comment whose body contains `marker`
+pub fn find_pr_comment(pr_number: u32, marker: &str) -> Result<Option<u64>> {
+    #[derive(Deserialize)]
+    struct Comment {
+        id: u64,
+        body: String,
+    }
+
+    let output = run_gh(&[
+        "api",
+        "--paginate",
+        &format!("repos/{{owner}}/{{repo}}/issues/{}/comments", pr_number),
+    ])?;
+    // --paginate concatenates one JSON array per page
+    let comments = serde_json::Deserializer::from_str(&output)
+        .into_iter::<Vec<Comment>>()
+        .collect::<Result<Vec<_>, _>>()
+        .context("Failed to parse comments JSON")?;
+
+    Ok(comments
+        .into_iter()
+        .flatten()
+        .find(|c| c.body.contains(marker))
+        .map(|c| c.id))
+}
+
+/// Add a comment to a PR
+pub fn create_pr_comment(pr_number: u32, body: &str) -> Result<()> {
+    run_gh(&["pr", "comment", &pr_number.to_string(), "--body", body])?;
+    Ok(())
+}
+
+/// Replace the body of an existing PR comment
+pub fn update_pr_comment(comment_id: u64, body: &str) -> Result<()> {
+    run_gh(&[
+        "api",
+        &format!("repos/{{owner}}/{{repo}}/issues/comments/{}", comment_id),
+        "-X",
+        "PATCH",
+        "-f",
+        &format!("body={}", body),
+    ])?;
+    Ok(())
+}
+
+/// Delete a PR comment
+pub fn delete_pr_comment(comment_id: u64) -> Result<()> {
+    run_gh(&[
+        "api",
+        &format!("repos/{{owner}}/{{repo}}/issues/comments/{}", comment_id),
+        "-X",
+        "DELETE",
+    ])?;
+    Ok(())
+}
+
+/// Get the body of a PR comment
+pub fn get_pr_comment_body(comment_id: u64) -> Result<String> {
+    run_gh(&[
+        "api",
+        &format!("repos/{{owner}}/{{repo}}/issues/comments/{}", comment_id),
+        "--jq",
+        ".body",
+    ])
+}
+
+/// Build `gh pr create`/`gh pr edit` flags for metadata. `prefix` is `--` for
+/// create and `--add-` for edit.
+fn metadata_args(metadata: &PrMetadata, prefix: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let lists = [
+        ("reviewer", &metadata.reviewers),
+        ("label", &metadata.labels),
+        ("assignee", &metadata.assignees),
+    ];
+    for (flag, values) in lists {
+        if !values.is_empty() {
+            args.push(format!("{}{}", prefix, flag));
+            args.push(values.join(","));
+        }
+    }
+    if let Some(milestone) = &metadata.milestone {
+        args.push("--milestone".to_string());
+        args.push(milestone.clone());
+    }
+    args
+}
+
+/// Get reviewers (requested or already reviewed), labels, assignees and milestone of a PR
+pub fn get_pr_metadata(pr_number: u32) -> Result<PrMetadata> {
+    #[derive(Deserialize)]
+    struct Login {
+        #[serde(default)]
+        login: String,
+    }
+    #[derive(Deserialize)]
+    struct Review {
+        author: Login,
+    }
+    #[derive(Deserialize)]
+    struct Requested {
+        #[serde(default)]
+        login: Option<String>,
+        #[serde(default)]
+        slug: Option<String>,
+    }
+    #[derive(Deserialize)]
+    struct Named {
+        #[serde(alias = "title")]
+        name: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct View {
+        review_requests: Vec<Requested>,
+        reviews: Vec<Review>,
+        labels: Vec<Named>,
+        assignees: Vec<Login>,
+        milestone: Option<Named>,
+    }
+
+    let output = run_gh(&[
+        "pr",
+        "view",
+        &pr_number.to_string(),
+        "--json",
+        "reviewRequests,reviews,labels,assignees,milestone",
+    ])?;
+    let view: View = serde_json::from_str(&output).context("Failed to parse PR JSON")?;
+
+    let mut reviewers: Vec<String> = view
+        .review_requests
+        .into_iter()
+        .filter_map(|r| r.login.or(r.slug))
+        .collect();
+    reviewers.extend(view.reviews.into_iter().map(|r| r.author.login));
+
+    Ok(PrMetadata {
+        reviewers,
+        labels: view.labels.into_iter().map(|l| l.name).collect(),
+        assignees: view.assignees.into_iter().map(|a| a.login).collect(),
+        milestone: view.milestone.map(|m| m.name),
+    })
+}
+
+/// Add reviewers, labels and assignees to a PR and set its milestone
+pub fn add_pr_metadata(pr_number: u32, metadata: &PrMetadata) -> Result<()> {
+    let number = pr_number.to_string();
+    let mut args = vec!["pr".to_string(), "edit".to_string(), number];
+    args.extend(metadata_args(metadata, "--add-"));
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run_gh(&args)?;
+    Ok(())
+}
+
+/// Login of the authenticated gh user
+pub fn current_user() -> Result<String> {
+    run_gh(&["api", "user", "--jq", ".login"])
+}
+
+/// Mark a draft PR as ready for review
+pub fn mark_pr_ready(pr_number: u32) -> Result<()> {
+    run_gh(&["pr", "ready", &pr_number.to_string()])?;
+    Ok(())
+}
+
+/// Convert a PR back to draft
+pub fn mark_pr_draft(pr_number: u32) -> Result<()> {
+    run_gh(&["pr", "ready", &pr_number.to_string(), "--undo"])?;
+    Ok(())
+}
+
 /// Update PR base branch
 pub fn update_pr_base(pr_number: u32, new_base: &str) -> Result<()> {
     run_gh(&[
         "api",
         &format!("repos/{{owner}}/{{repo}}/pulls/{}", pr_number),
-        "-X", "PATCH",
-        "-f", &format!("base={}", new_base),
+        "-X",
+        "PATCH",
+        "-f",
+        &format!("base={}", new_base),
     ])?;
     Ok(())
 }
 
+/// Title and description of a PR
+#[derive(Debug, Deserialize)]
+pub struct PrContent {
+    pub title: String,
+    pub body: String,
+}
+
+/// Get the current PR title and body
+pub fn get_pr_content(pr_number: u32) -> Result<PrContent> {
+    let output = run_gh(&["pr", "view", &pr_number.to_string(), "--json", "title,body"])?;
+    serde_json::from_str(&output).context("Failed to parse PR JSON")
+}
+
+/// Update PR title
+pub fn update_pr_title(pr_number: u32, title: &str) -> Result<()> {
+    run_gh(&["pr", "edit", &pr_number.to_string(), "--title", title])?;
+    Ok(())
+}
+
 /// Update PR body
 pub fn update_pr_body(pr_number: u32, body: &str) -> Result<()> {
     run_gh(&["pr", "edit", &pr_number.to_string(), "--body", body])?;
     Ok(())
 }
 
+/// Merge a PR with `method` (squash, merge or rebase), or enable auto-merge when `auto` is set
+pub fn merge_pr(pr_number: u32, auto: bool, method: &str) -> Result<()> {
+    let number = pr_number.to_string();
+    let method = format!("--{}", method);
+    let mut args = vec!["pr", "merge", &number, &method];
+    if auto {
+        args.push("--auto");
+    }
+    run_gh(&args)?;
+    Ok(())
+}
+
 /// Get PR status (OPEN, MERGED, CLOSED)
 pub fn get_pr_status(pr_number: u32) -> Result<String> {
-    let output = Command::new("gh")
-        .args([
-            "pr", "view", &pr_number.to_string(),
-            "--json", "state",
-            "--jq", ".state"
-        ])
-        .output()
-        .context("Failed to get PR status")?;
+    let output = gh_output(&[
+        "pr",
+        "view",
+        &pr_number.to_string(),
+        "--json",
+        "state",
+        "--jq",
+        ".state",
+    ])
+    .context("Failed to get PR status")?;
 
     if output.status.success() {
-        let status = String::from_utf8_lossy(&output.stdout).trim().to_uppercase();
-        Ok(if status.is_empty() { "UNKNOWN".to_string() } else { status })
+        let status = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .to_uppercase();
+        Ok(if status.is_empty() {
+            "UNKNOWN".to_string()
+        } else {
+            status
+        })
     } else {
         Ok("UNKNOWN".to_string())
     }
@@ -113,18 +560,26 @@ pub fn get_pr_status(pr_number: u32) -> Result<String> {
 
 /// Get CI status (SUCCESS, PENDING, FAILURE)
 pub fn get_ci_status(pr_number: u32) -> Result<String> {
-    let output = Command::new("gh")
-        .args([
-            "pr", "view", &pr_number.to_string(),
-            "--json", "statusCheckRollup",
-            "--jq", ".statusCheckRollup[0].conclusion // \"PENDING\""
-        ])
-        .output()
-        .context("Failed to get CI status")?;
+    let output = gh_output(&[
+        "pr",
+        "view",
+        &pr_number.to_string(),
+        "--json",
+        "statusCheckRollup",
+        "--jq",
+        ".statusCheckRollup[0].conclusion // \"PENDING\"",
+    ])
+    .context("Failed to get CI status")?;
 
     if output.status.success() {
-        let status = String::from_utf8_lossy(&output.stdout).trim().to_uppercase();
-        Ok(if status.is_empty() { "PENDING".to_string() } else { status })
+        let status = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .to_uppercase();
+        Ok(if status.is_empty() {
+            "PENDING".to_string()
+        } else {
+            status
+        })
     } else {
         Ok("UNKNOWN".to_string())
     }
@@ -132,18 +587,26 @@ pub fn get_ci_status(pr_number: u32) -> Result<String> {
 
 /// Get review status (APPROVED, PENDING, CHANGES_REQUESTED)
 pub fn get_review_status(pr_number: u32) -> Result<String> {
-    let output = Command::new("gh")
-        .args([
-            "pr", "view", &pr_number.to_string(),
-            "--json", "reviewDecision",
-            "--jq", ".reviewDecision // \"PENDING\""
-        ])
-        .output()
-        .context("Failed to get review status")?;
+    let output = gh_output(&[
+        "pr",
+        "view",
+        &pr_number.to_string(),
+        "--json",
+        "reviewDecision",
+        "--jq",
+        ".reviewDecision // \"PENDING\"",
+    ])
+    .context("Failed to get review status")?;
 
     if output.status.success() {
-        let status = String::from_utf8_lossy(&output.stdout).trim().to_uppercase();
-        Ok(if status.is_empty() { "PENDING".to_string() } else { status })
+        let status = String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .to_uppercase();
+        Ok(if status.is_empty() {
+            "PENDING".to_string()
+        } else {
+            status
+        })
     } else {
         Ok("UNKNOWN".to_string())
     }
@@ -0,0 +1,153 @@
+use crate::{error::FlowError, stack};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LOCK_FILE: &str = "gh-flow.lock";
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// A lock file still empty after this long was left by a process that died
+/// while creating it
+const UNREADABLE_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Process holding the lock, as recorded in the lock file
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Holder {
+    pid: u32,
+    command: String,
+    /// Unix time the lock was taken
+    started: u64,
+}
+
+/// Advisory lock on the stack of the current repository, released on drop
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn command_line() -> String {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    format!("gh flow {}", args.join(" "))
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+#[cfg(windows)]
+fn is_running(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+        .unwrap_or(true)
+}
+
+fn read_holder(path: &Path) -> Option<Holder> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn is_old(path: &Path, age: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|elapsed| elapsed > age)
+}
+
+/// Take the lock held by commands that change the stack, branches or PRs.
+/// With `wait`, block until another gh-flow operation finishes instead of
+/// failing. Returns `None` outside a repository gh-flow can identify, where
+/// the command fails with a better error of its own.
+pub fn acquire(wait: bool) -> Result<Option<Lock>> {
+    let Ok(dir) = stack::get_repo_config_dir() else {
+        return Ok(None);
+    };
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(LOCK_FILE);
+
+    let me = Holder {
+        pid: std::process::id(),
+        command: command_line(),
+        started: now(),
+    };
+    let mut waiting = false;
+
+    loop {
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(serde_json::to_string(&me)?.as_bytes())
+                    .with_context(|| format!("Failed to write {}", path.display()))?;
+                return Ok(Some(Lock { path }));
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {}", path.display()))
+            }
+        }
+
+        match read_holder(&path) {
+            // Being written by another process right now, or left empty by one that died
+            None => {
+                if is_old(&path, UNREADABLE_LOCK_TIMEOUT) {
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+            }
+            Some(holder) if holder.pid == me.pid || !is_running(holder.pid) => {
+                eprintln!(
+                    "{} Removing stale lock left by `{}` (pid {})",
+                    "⚠".yellow(),
+                    holder.command,
+                    holder.pid
+                );
+                // Only if nobody replaced it in the meantime
+                if read_holder(&path).as_ref() == Some(&holder) {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            }
+            Some(holder) if wait => {
+                if !waiting {
+                    eprintln!(
+                        "Waiting for `{}` (pid {}) to finish...",
+                        holder.command, holder.pid
+                    );
+                    waiting = true;
+                }
+            }
+            Some(holder) => {
+                return Err(FlowError::Locked {
+                    pid: holder.pid,
+                    command: holder.command,
+                    seconds: now().saturating_sub(holder.started),
+                    path,
+                }
+                .into())
+            }
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
@@ -1,13 +1,30 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use clap_complete::Shell;
+use colored::Colorize;
+use error::FlowError;
+use stack::{DraftPolicy, TitleStrategy};
+use std::path::PathBuf;
+use validation::Check;
 
+mod autostash;
+mod codeowners;
 mod commands;
+mod error;
 mod git;
 mod github;
+mod lock;
 mod progress;
+mod schema;
+mod settings;
 mod stack;
+mod storage;
+mod template;
+#[cfg(test)]
+mod testing;
+mod tools;
 mod validation;
+mod worktree;
 
 #[derive(Parser)]
 #[command(
@@ -18,25 +35,95 @@ mod validation;
 pub struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Wait for another running gh-flow operation to finish instead of failing
+    #[arg(long, global = true)]
+    wait: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize a new PR stack
     Init {
-        /// Base branch (default: main)
-        #[arg(short, long, default_value = "main")]
-        base: String,
+        /// Base branch (default: the `base-branch` setting, main unless configured)
+        #[arg(short, long)]
+        base: Option<String>,
+
+        /// Remote to push stack branches to (default: origin)
+        #[arg(long)]
+        remote: Option<String>,
+
+        /// Remote of the repository to open PRs against, e.g. when pushing to a fork
+        /// (default: the push remote)
+        #[arg(long)]
+        upstream: Option<String>,
+
+        /// Where to keep stack metadata; `refs` shares it with teammates through the remote
+        #[arg(long, value_enum)]
+        storage: Option<storage::Storage>,
+    },
+
+    /// Adopt existing PRs into the stack (default: the current branch's PR)
+    Adopt {
+        /// PR numbers to adopt, bottom-most first
+        prs: Vec<u32>,
+
+        /// Insert the PRs after this branch instead of at the top; the branches
+        /// above are rebased onto them
+        #[arg(long, value_name = "BRANCH")]
+        after: Option<String>,
+
+        /// Rebase the adopted branches onto their new parent
+        #[arg(long)]
+        rebase: bool,
+
+        /// Stash uncommitted changes and restore them afterwards
+        /// (default: the `autostash` setting)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        autostash: Option<bool>,
+    },
+
+    /// Import a stack from a file written by `export`, or from another stacking tool
+    Import {
+        /// Exported stack (JSON or YAML), `-` for stdin
+        #[arg(required_unless_present = "from", conflicts_with = "from")]
+        file: Option<PathBuf>,
+
+        /// Read the current stack from another tool's metadata instead
+        #[arg(long, value_enum)]
+        from: Option<tools::Tool>,
+    },
+
+    /// Export the stack as JSON or YAML, or to another stacking tool
+    Export {
+        /// Write the stack into another tool's metadata instead
+        #[arg(long, value_enum, conflicts_with_all = ["format", "output"])]
+        to: Option<tools::ExportTool>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "json")]
+        format: schema::Format,
+
+        /// File to write (default: stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
     },
 
-    /// Adopt an existing PR into the stack
-    Adopt,
+    /// Check out the stack a PR belongs to, with local branches for every PR in it
+    Get {
+        /// Number of any PR in the stack
+        pr: u32,
+    },
 
     /// Show the status of the current PR stack
     Status,
 
     /// Synchronize the entire stack (rebase + retarget PRs)
     Sync {
+        /// Sync every tracked stack without switching branches
+        #[arg(short, long, conflicts_with = "autostash")]
+        all: bool,
+
         /// Dry run - show what would be done without doing it
         #[arg(short, long)]
         dry_run: bool,
@@ -44,6 +131,11 @@ enum Commands {
         /// Wait for CI to pass before syncing
         #[arg(long)]
         wait_ci: bool,
+
+        /// Stash uncommitted changes and restore them afterwards
+        /// (default: the `autostash` setting)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        autostash: Option<bool>,
     },
 
     /// Push all branches in the stack
@@ -53,6 +145,17 @@ enum Commands {
         force: bool,
     },
 
+    /// Push changed branches, create missing PRs and update existing ones
+    Submit {
+        /// Create new PRs as drafts
+        #[arg(long)]
+        draft: bool,
+
+        /// Dry run - show every planned action without doing it
+        #[arg(short, long)]
+        dry_run: bool,
+    },
+
     /// Create PRs for the entire stack
     Pr {
         #[command(subcommand)]
@@ -68,6 +171,25 @@ enum Commands {
         /// Wait for CI to pass before merging
         #[arg(long)]
         wait_ci: bool,
+
+        /// How to merge, instead of the `merge-method` setting
+        #[arg(long, value_enum)]
+        method: Option<commands::merge::MergeMethod>,
+    },
+
+    /// Mark draft PRs in the stack ready for review
+    Ready {
+        /// Mark every open PR in the stack ready
+        #[arg(long, conflicts_with = "bottom")]
+        all: bool,
+
+        /// Mark only the bottom-most open PR ready
+        #[arg(long)]
+        bottom: bool,
+
+        /// Set the stack's draft policy
+        #[arg(long, value_enum)]
+        policy: Option<DraftPolicy>,
     },
 
     /// Split the stack into separate stacks
@@ -75,6 +197,46 @@ enum Commands {
         /// Interactive mode to select branches
         #[arg(short, long)]
         interactive: bool,
+
+        /// Stash uncommitted changes and restore them afterwards
+        /// (default: the `autostash` setting)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        autostash: Option<bool>,
+    },
+
+    /// Delete branches whose PRs were merged or closed, or whose remote branch is gone
+    Prune {
+        /// Show what would be pruned without deleting anything
+        #[arg(short, long)]
+        dry_run: bool,
+
+        /// Only prune branches whose last commit is older than this, e.g. 30d, 2w or 12h
+        #[arg(long, value_parser = commands::prune::parse_age)]
+        older_than: Option<std::time::Duration>,
+    },
+
+    /// Diagnose stack inconsistencies, and repair them with --fix
+    Doctor {
+        /// Repair every problem that can be repaired automatically
+        #[arg(long)]
+        fix: bool,
+
+        /// Stash uncommitted changes while repairing and restore them afterwards
+        /// (default: the `autostash` setting)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true", requires = "fix")]
+        autostash: Option<bool>,
+    },
+
+    /// Show and change settings (base branch, merge method, PR defaults)
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Manage the PR description template
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
     },
 
     /// Generate shell completions
@@ -92,45 +254,312 @@ enum PrAction {
         /// Create as draft PRs
         #[arg(short, long)]
         draft: bool,
+
+        /// Post the stack table as a PR comment instead of in the description (saved for the stack)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        comment: Option<bool>,
+
+        /// How to derive PR titles (saved for the stack)
+        #[arg(long, value_enum)]
+        title_strategy: Option<TitleStrategy>,
+
+        /// Prefix titles with the PR's position, e.g. [2/5] (saved for the stack)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        position_prefix: Option<bool>,
+
+        #[command(flatten)]
+        metadata: commands::pr::MetadataOptions,
     },
 
     /// Update existing PRs
-    Update,
+    Update {
+        /// Where to insert the stack section in PRs that don't have one yet
+        #[arg(long, value_enum, default_value = "bottom")]
+        placement: commands::pr::SectionPlacement,
+
+        /// Keep the stack table in a single sticky PR comment instead of the description
+        /// (saved for the stack; `--comment=false` moves it back)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        comment: Option<bool>,
+
+        /// Re-sync PR titles (e.g. after positions in the stack changed)
+        #[arg(long)]
+        titles: bool,
+
+        /// How to derive PR titles (saved for the stack)
+        #[arg(long, value_enum)]
+        title_strategy: Option<TitleStrategy>,
+
+        /// Prefix titles with the PR's position, e.g. [2/5] (saved for the stack)
+        #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+        position_prefix: Option<bool>,
+
+        #[command(flatten)]
+        metadata: commands::pr::MetadataOptions,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective value of a setting
+    Get {
+        key: String,
+
+        /// Also print which file or variable the value comes from
+        #[arg(long)]
+        show_origin: bool,
+    },
+
+    /// Change a setting (default: for you, in this repository only)
+    Set {
+        key: String,
+
+        /// New value; lists are comma-separated
+        value: String,
+
+        /// Write to ~/.config/gh-flow/config.toml, for every repository
+        #[arg(long, conflicts_with = "repo")]
+        global: bool,
+
+        /// Write to .github/gh-flow.toml, to commit and share with the team
+        #[arg(long)]
+        repo: bool,
+    },
+
+    /// Remove a setting, falling back to the next layer
+    Unset {
+        key: String,
+
+        /// Remove from ~/.config/gh-flow/config.toml
+        #[arg(long, conflicts_with = "repo")]
+        global: bool,
+
+        /// Remove from .github/gh-flow.toml
+        #[arg(long)]
+        repo: bool,
+    },
+
+    /// List every setting with its effective value
+    List {
+        /// Also print which file or variable each value comes from
+        #[arg(long)]
+        show_origin: bool,
+    },
 }
 
-fn main() -> Result<()> {
+#[derive(Subcommand)]
+enum TemplateAction {
+    /// Render the template against the current stack and report unknown variables
+    Check {
+        /// Print the rendered description for each branch
+        #[arg(long)]
+        show: bool,
+    },
+}
+
+impl Commands {
+    /// Whether the command changes the stack, branches or PRs, and so must
+    /// not run alongside another gh-flow operation
+    fn mutates(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Status
+                | Commands::Export { .. }
+                | Commands::Sync { dry_run: true, .. }
+                | Commands::Submit { dry_run: true, .. }
+                | Commands::Prune { dry_run: true, .. }
+                | Commands::Doctor { fix: false, .. }
+                | Commands::Config {
+                    action: ConfigAction::Get { .. } | ConfigAction::List { .. }
+                }
+                | Commands::Template { .. }
+                | Commands::Completions { .. }
+        )
+    }
+
+    /// Conditions checked before the command starts
+    fn preflight(&self) -> Vec<Check> {
+        use Check::*;
+        match self {
+            Commands::Init { .. } | Commands::Export { .. } | Commands::Template { .. } => {
+                vec![GitRepo]
+            }
+            Commands::Status | Commands::Import { .. } => vec![GitRepo, OnBranch],
+            Commands::Adopt { .. } => vec![GitRepo, GhAuth, OnBranch, NoRebaseInProgress],
+            Commands::Get { .. } => vec![GitRepo, GhAuth, NoRebaseInProgress],
+            Commands::Sync { .. } => vec![GitRepo, GhAuth, OnBranch, NoRebaseInProgress],
+            Commands::Push { .. } => vec![GitRepo, NoRebaseInProgress],
+            Commands::Submit { .. } | Commands::Prune { .. } => {
+                vec![GitRepo, GhAuth, NoRebaseInProgress]
+            }
+            Commands::Split { .. } => vec![GitRepo, GhAuth, OnBranch, NoRebaseInProgress],
+            Commands::Pr { .. } | Commands::Merge { .. } | Commands::Ready { .. } => {
+                vec![GitRepo, GhAuth]
+            }
+            // Repairs rebase branches; diagnosing works anywhere
+            Commands::Doctor { fix: true, .. } => vec![GitRepo, OnBranch, NoRebaseInProgress],
+            // doctor diagnoses these itself; config and completions work anywhere
+            Commands::Doctor { .. } | Commands::Config { .. } | Commands::Completions { .. } => {
+                vec![]
+            }
+        }
+    }
+}
+
+fn main() {
+    if let Err(e) = run() {
+        let flow_error = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<FlowError>());
+        eprintln!("{} {:?}", "Error:".red().bold(), e);
+        if let Some(suggestion) = flow_error.and_then(FlowError::suggestion) {
+            eprintln!("\n{}\n  {}", "Suggestion:".yellow(), suggestion);
+        }
+        std::process::exit(flow_error.map_or(1, FlowError::exit_code));
+    }
+}
+
+fn run() -> Result<()> {
     let cli = Cli::parse();
 
+    validation::preflight(&cli.command.preflight())?;
+
+    let _lock = if cli.command.mutates() {
+        lock::acquire(cli.wait)?
+    } else {
+        None
+    };
+
     match cli.command {
-        Commands::Init { base } => {
-            commands::init::run(&base)?;
+        Commands::Init {
+            base,
+            remote,
+            upstream,
+            storage,
+        } => {
+            commands::init::run(
+                base.as_deref(),
+                remote.as_deref(),
+                upstream.as_deref(),
+                storage,
+            )?;
         }
-        Commands::Adopt => {
-            commands::adopt::run()?;
+        Commands::Adopt {
+            prs,
+            after,
+            rebase,
+            autostash,
+        } => {
+            commands::adopt::run(&prs, after.as_deref(), rebase, autostash)?;
+        }
+        Commands::Import { file, from } => {
+            commands::import::run(file.as_deref(), from)?;
+        }
+        Commands::Export { to, format, output } => {
+            commands::export::run(to, format, output.as_deref())?;
+        }
+        Commands::Get { pr } => {
+            commands::get::run(pr)?;
         }
         Commands::Status => {
             commands::status::run()?;
         }
-        Commands::Sync { dry_run, wait_ci } => {
-            commands::sync::run(dry_run, wait_ci)?;
+        Commands::Sync {
+            all,
+            dry_run,
+            wait_ci,
+            autostash,
+        } => {
+            commands::sync::run(all, dry_run, wait_ci, autostash)?;
         }
         Commands::Push { force } => {
             commands::push::run(force)?;
         }
+        Commands::Submit { draft, dry_run } => {
+            commands::submit::run(draft, dry_run)?;
+        }
         Commands::Pr { action } => match action {
-            PrAction::Create { draft } => {
-                commands::pr::create(draft)?;
+            PrAction::Create {
+                draft,
+                comment,
+                title_strategy,
+                position_prefix,
+                metadata,
+            } => {
+                let title_options = commands::pr::TitleOptions {
+                    strategy: title_strategy,
+                    position_prefix,
+                };
+                commands::pr::create(draft, comment, title_options, metadata)?;
             }
-            PrAction::Update => {
-                commands::pr::update()?;
+            PrAction::Update {
+                placement,
+                comment,
+                titles,
+                title_strategy,
+                position_prefix,
+                metadata,
+            } => {
+                let title_options = commands::pr::TitleOptions {
+                    strategy: title_strategy,
+                    position_prefix,
+                };
+                commands::pr::update(placement, comment, titles, title_options, metadata)?;
             }
         },
-        Commands::Merge { auto, wait_ci } => {
-            commands::merge::run(auto, wait_ci)?;
+        Commands::Merge {
+            auto,
+            wait_ci,
+            method,
+        } => {
+            commands::merge::run(auto, wait_ci, method)?;
+        }
+        Commands::Ready {
+            all,
+            bottom,
+            policy,
+        } => {
+            commands::ready::run(all, bottom, policy)?;
+        }
+        Commands::Split {
+            interactive,
+            autostash,
+        } => {
+            commands::split::run(interactive, autostash)?;
+        }
+        Commands::Prune {
+            dry_run,
+            older_than,
+        } => {
+            commands::prune::run(dry_run, older_than)?;
         }
-        Commands::Split { interactive } => {
-            commands::split::run(interactive)?;
+        Commands::Doctor { fix, autostash } => {
+            commands::doctor::run(fix, autostash)?;
         }
+        Commands::Config { action } => match action {
+            ConfigAction::Get { key, show_origin } => {
+                commands::config::get(&key, show_origin)?;
+            }
+            ConfigAction::Set {
+                key,
+                value,
+                global,
+                repo,
+            } => {
+                commands::config::set(&key, Some(&value), commands::config::scope(global, repo))?;
+            }
+            ConfigAction::Unset { key, global, repo } => {
+                commands::config::set(&key, None, commands::config::scope(global, repo))?;
+            }
+            ConfigAction::List { show_origin } => {
+                commands::config::list(show_origin)?;
+            }
+        },
+        Commands::Template { action } => match action {
+            TemplateAction::Check { show } => {
+                commands::template::check(show)?;
+            }
+        },
         Commands::Completions { shell } => {
             commands::completions::run(shell)?;
         }
@@ -0,0 +1,142 @@
+use crate::stack::{BranchInfo, StackConfig};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// Version of the stack export format. Bumped whenever a change would make
+/// older gh-flow versions misread a file.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Serialization format of an exported stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    Json,
+    Yaml,
+}
+
+/// A stack definition that can be moved between machines
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StackExport {
+    /// Schema version, see [`SCHEMA_VERSION`]
+    pub version: u32,
+    /// Branch the bottom of the stack is based on
+    pub base: String,
+    /// Branches from the bottom of the stack to the top
+    pub branches: Vec<ExportedBranch>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedBranch {
+    pub name: String,
+    pub parent: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pr_number: Option<u32>,
+    /// Commit the branch pointed to when it was exported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<String>,
+}
+
+impl StackExport {
+    /// Describe a stack, with each branch's current head commit from `head_of`
+    pub fn from_config(config: &StackConfig, head_of: impl Fn(&str) -> Option<String>) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            base: config.base_branch.clone(),
+            branches: config
+                .branches
+                .iter()
+                .map(|b| ExportedBranch {
+                    name: b.name.clone(),
+                    parent: b.parent.clone(),
+                    pr_number: b.pr_number,
+                    head: head_of(&b.name),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn render(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Json => serde_json::to_string_pretty(self)? + "\n",
+            Format::Yaml => serde_norway::to_string(self)?,
+        })
+    }
+
+    /// Parse an exported stack. YAML is a superset of JSON, so both are accepted.
+    pub fn parse(content: &str) -> Result<Self> {
+        // Check the version first, so newer files get a useful error instead
+        // of a complaint about fields this version doesn't know
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: Option<u32>,
+        }
+        let versioned: Versioned =
+            serde_norway::from_str(content).context("Not a gh-flow stack export")?;
+        match versioned.version {
+            Some(SCHEMA_VERSION) => {}
+            Some(version) => anyhow::bail!(
+                "Stack export has schema version {}, but this gh-flow only reads version {}",
+                version,
+                SCHEMA_VERSION
+            ),
+            None => anyhow::bail!("Not a gh-flow stack export: missing `version`"),
+        }
+
+        serde_norway::from_str(content).context("Invalid gh-flow stack export")
+    }
+
+    /// Stack configuration holding just the exported branches
+    pub fn into_config(self) -> StackConfig {
+        StackConfig {
+            base_branch: self.base,
+            branches: self
+                .branches
+                .into_iter()
+                .map(|b| BranchInfo {
+                    name: b.name,
+                    parent: b.parent,
+                    pr_number: b.pr_number,
+                    pushed_sha: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export() -> StackExport {
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        config.add_branch("a".to_string(), "main".to_string());
+        config.add_branch("b".to_string(), "a".to_string());
+        config.branches[0].pr_number = Some(12);
+        StackExport::from_config(&config, |branch| Some(format!("{}-sha", branch)))
+    }
+
+    #[test]
+    fn round_trips_through_json_and_yaml() {
+        for format in [Format::Json, Format::Yaml] {
+            let parsed = StackExport::parse(&export().render(format).unwrap()).unwrap();
+            assert_eq!(parsed.base, "main");
+            assert_eq!(parsed.branches.len(), 2);
+            assert_eq!(parsed.branches[0].pr_number, Some(12));
+            assert_eq!(parsed.branches[1].parent, "a");
+            assert_eq!(parsed.branches[1].head.as_deref(), Some("b-sha"));
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions_and_unknown_fields() {
+        let newer = StackExport::parse("version: 2\nbase: main\nbranches: []\n").unwrap_err();
+        assert!(newer.to_string().contains("schema version 2"));
+        assert!(StackExport::parse("base: main\nbranches: []\n").is_err());
+        assert!(StackExport::parse("version: 1\nbase: main\nbranches: []\nextra: 1\n").is_err());
+    }
+}
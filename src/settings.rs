@@ -0,0 +1,451 @@
+use crate::git;
+use crate::stack::{self, PrSettings};
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+/// Team settings committed to the repository, relative to its root
+pub const REPO_SETTINGS_FILE: &str = ".github/gh-flow.toml";
+/// Settings for every repository, in the global config directory
+const GLOBAL_SETTINGS_FILE: &str = "config.toml";
+/// Prefix of environment variables overriding a setting, e.g. `GH_FLOW_BASE_BRANCH`
+const ENV_PREFIX: &str = "GH_FLOW_";
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    String,
+    Bool,
+    /// Strings; comma-separated on the command line and in env vars
+    List,
+    Choice(&'static [&'static str]),
+}
+
+/// A setting gh-flow understands
+pub struct Key {
+    pub name: &'static str,
+    kind: Kind,
+    /// Default in the command-line syntax
+    default: &'static str,
+    pub description: &'static str,
+}
+
+pub const KEYS: &[Key] = &[
+    Key {
+        name: "base-branch",
+        kind: Kind::String,
+        default: "main",
+        description: "Branch new stacks are based on",
+    },
+    Key {
+        name: "merge-method",
+        kind: Kind::Choice(&["squash", "merge", "rebase"]),
+        default: "squash",
+        description: "How `gh flow merge` merges PRs",
+    },
+    Key {
+        name: "autostash",
+        kind: Kind::Bool,
+        default: "false",
+        description: "Stash uncommitted changes while sync, split and adopt rewrite branches",
+    },
+    Key {
+        name: "branch-prefix",
+        kind: Kind::String,
+        default: "",
+        description: "Prefix stack branch names are expected to start with, e.g. `feature/`",
+    },
+    Key {
+        name: "pr.draft-policy",
+        kind: Kind::Choice(&["manual", "bottom-ready"]),
+        default: "manual",
+        description: "Which PRs of the stack are kept as drafts",
+    },
+    Key {
+        name: "pr.title-strategy",
+        kind: Kind::Choice(&["first-commit", "last-commit", "branch-name"]),
+        default: "last-commit",
+        description: "How PR titles are derived from a branch",
+    },
+    Key {
+        name: "pr.title-position-prefix",
+        kind: Kind::Bool,
+        default: "false",
+        description: "Prefix PR titles with their position in the stack, e.g. `[2/5]`",
+    },
+    Key {
+        name: "pr.reviewers",
+        kind: Kind::List,
+        default: "",
+        description: "Users to request reviews from",
+    },
+    Key {
+        name: "pr.team-reviewers",
+        kind: Kind::List,
+        default: "",
+        description: "Teams to request reviews from, as `team` or `org/team`",
+    },
+    Key {
+        name: "pr.labels",
+        kind: Kind::List,
+        default: "",
+        description: "Labels added to new PRs",
+    },
+    Key {
+        name: "pr.assignees",
+        kind: Kind::List,
+        default: "",
+        description: "Users assigned to new PRs, `@me` for yourself",
+    },
+    Key {
+        name: "pr.milestone",
+        kind: Kind::String,
+        default: "",
+        description: "Milestone new PRs are added to",
+    },
+    Key {
+        name: "pr.codeowners",
+        kind: Kind::Bool,
+        default: "false",
+        description: "Request reviews from the CODEOWNERS of each branch's changed files",
+    },
+    Key {
+        name: "pr.stack-comment",
+        kind: Kind::Bool,
+        default: "false",
+        description: "Keep the stack table in a sticky PR comment instead of the description",
+    },
+];
+
+/// Find a setting by name
+pub fn key(name: &str) -> Result<&'static Key> {
+    KEYS.iter().find(|key| key.name == name).with_context(|| {
+        format!(
+            "Unknown setting '{}'. Run `gh flow config list` to see all settings.",
+            name
+        )
+    })
+}
+
+impl Key {
+    /// Environment variable overriding this setting
+    pub fn env_var(&self) -> String {
+        format!(
+            "{}{}",
+            ENV_PREFIX,
+            self.name.to_uppercase().replace(['.', '-'], "_")
+        )
+    }
+
+    /// Parse a value given on the command line or in an environment variable
+    pub fn parse(&self, raw: &str) -> Result<Value> {
+        let raw = raw.trim();
+        match self.kind {
+            Kind::String => Ok(Value::from(raw)),
+            Kind::Bool => match raw {
+                "true" | "yes" | "on" | "1" => Ok(Value::Bool(true)),
+                "false" | "no" | "off" | "0" => Ok(Value::Bool(false)),
+                _ => anyhow::bail!("{} must be true or false, got '{}'", self.name, raw),
+            },
+            Kind::List => Ok(Value::from(
+                raw.split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .collect::<Vec<_>>(),
+            )),
+            Kind::Choice(choices) => self
+                .check(Value::from(raw))
+                .with_context(|| format!("{} must be one of: {}", self.name, choices.join(", "))),
+        }
+    }
+
+    /// Check the type of a value read from a settings file
+    fn check(&self, value: Value) -> Result<Value> {
+        let valid = match (self.kind, &value) {
+            (Kind::String, Value::String(_)) | (Kind::Bool, Value::Bool(_)) => true,
+            (Kind::List, Value::Array(items)) => items.iter().all(Value::is_string),
+            (Kind::Choice(choices), Value::String(s)) => choices.contains(&s.as_str()),
+            _ => false,
+        };
+        if !valid {
+            anyhow::bail!("Invalid value for {}: {}", self.name, value);
+        }
+        Ok(value)
+    }
+}
+
+/// Render a value the way `gh flow config set` takes it
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Where a setting can be written, from the widest to the narrowest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// ~/.config/gh-flow/config.toml, for every repository
+    Global,
+    /// .github/gh-flow.toml, committed and shared with the team
+    Repo,
+    /// This repository's stack config, for this user only
+    User,
+}
+
+/// Layer an effective value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    File(Scope, PathBuf),
+    User,
+    Env(String),
+}
+
+impl Origin {
+    /// Layer a value set with `gh flow config set` ends up in
+    pub fn scope(&self) -> Option<Scope> {
+        match self {
+            Origin::File(scope, _) => Some(*scope),
+            Origin::User => Some(Scope::User),
+            Origin::Default | Origin::Env(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::File(Scope::Global, path) => write!(f, "global:{}", path.display()),
+            Origin::File(_, path) => write!(f, "repo:{}", path.display()),
+            Origin::User => write!(f, "user"),
+            Origin::Env(var) => write!(f, "env:{}", var),
+        }
+    }
+}
+
+fn global_path() -> Result<PathBuf> {
+    Ok(stack::get_global_config_dir()?.join(GLOBAL_SETTINGS_FILE))
+}
+
+fn repo_path() -> Result<PathBuf> {
+    let root = git::run(&["rev-parse", "--show-toplevel"]).context("Not in a git repository")?;
+    Ok(Path::new(&root).join(REPO_SETTINGS_FILE))
+}
+
+/// Path of the settings file of `scope`, if it lives in a file
+pub fn scope_path(scope: Scope) -> Result<Option<PathBuf>> {
+    match scope {
+        Scope::Global => global_path().map(Some),
+        Scope::Repo => repo_path().map(Some),
+        Scope::User => Ok(None),
+    }
+}
+
+fn to_json(value: &toml_edit::Value) -> Option<Value> {
+    match value {
+        toml_edit::Value::String(s) => Some(Value::from(s.value().as_str())),
+        toml_edit::Value::Boolean(b) => Some(Value::Bool(*b.value())),
+        toml_edit::Value::Array(items) => items
+            .iter()
+            .map(to_json)
+            .collect::<Option<Vec<_>>>()
+            .map(Value::from),
+        _ => None,
+    }
+}
+
+fn to_toml(value: &Value) -> toml_edit::Item {
+    match value {
+        Value::Bool(b) => toml_edit::value(*b),
+        Value::Array(items) => toml_edit::value(
+            items
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<toml_edit::Array>(),
+        ),
+        other => toml_edit::value(display_value(other)),
+    }
+}
+
+/// Flatten a settings file into dotted keys: `[pr] labels = [...]` is `pr.labels`
+fn flatten(table: &toml_edit::Table, prefix: &str, out: &mut Vec<(String, Option<Value>)>) {
+    for (name, item) in table.iter() {
+        let name = format!("{}{}", prefix, name);
+        match item {
+            toml_edit::Item::Table(table) => flatten(table, &format!("{}.", name), out),
+            toml_edit::Item::Value(toml_edit::Value::InlineTable(table)) => {
+                flatten(&table.clone().into_table(), &format!("{}.", name), out)
+            }
+            toml_edit::Item::Value(value) => out.push((name, to_json(value))),
+            _ => {}
+        }
+    }
+}
+
+fn read_document(path: &Path) -> Result<Option<DocumentMut>> {
+    let Ok(content) = fs::read_to_string(path) else {
+        return Ok(None);
+    };
+    let document = content
+        .parse::<DocumentMut>()
+        .with_context(|| format!("{} is not valid TOML", path.display()))?;
+    Ok(Some(document))
+}
+
+/// Settings resolved from every layer: built-in defaults, the global file,
+/// the repository's committed file, the user's stack config, then
+/// environment variables. Command-line flags are applied on top by each command.
+pub struct Settings {
+    values: BTreeMap<&'static str, (Value, Origin)>,
+    /// Keys in settings files this gh-flow doesn't know, e.g. from a newer version
+    pub unknown: Vec<(String, Origin)>,
+}
+
+impl Settings {
+    /// Resolve the settings, with `user` the overrides saved in the stack config
+    pub fn resolve(user: &BTreeMap<String, Value>) -> Result<Self> {
+        let mut settings = Self {
+            values: BTreeMap::new(),
+            unknown: Vec::new(),
+        };
+        for key in KEYS {
+            let default = key.parse(key.default)?;
+            settings.values.insert(key.name, (default, Origin::Default));
+        }
+
+        for scope in [Scope::Global, Scope::Repo] {
+            // Outside a repository there is no committed file
+            let Ok(Some(path)) = scope_path(scope) else {
+                continue;
+            };
+            let Some(document) = read_document(&path)? else {
+                continue;
+            };
+            let mut entries = Vec::new();
+            flatten(document.as_table(), "", &mut entries);
+            for (name, value) in entries {
+                let origin = Origin::File(scope, path.clone());
+                if KEYS.iter().all(|key| key.name != name) {
+                    settings.unknown.push((name, origin));
+                    continue;
+                }
+                let value = value.with_context(|| {
+                    format!("Unsupported value for {} in {}", name, path.display())
+                })?;
+                settings
+                    .set(&name, value, origin)
+                    .with_context(|| format!("Invalid setting in {}", path.display()))?;
+            }
+        }
+
+        for (name, value) in user {
+            settings
+                .set(name, value.clone(), Origin::User)
+                .context("Invalid setting in the stack configuration")?;
+        }
+
+        for key in KEYS {
+            let var = key.env_var();
+            if let Ok(raw) = std::env::var(&var) {
+                let value = key
+                    .parse(&raw)
+                    .with_context(|| format!("Invalid value in {}", var))?;
+                settings.values.insert(key.name, (value, Origin::Env(var)));
+            }
+        }
+
+        Ok(settings)
+    }
+
+    fn set(&mut self, name: &str, value: Value, origin: Origin) -> Result<()> {
+        match KEYS.iter().find(|key| key.name == name) {
+            Some(key) => {
+                self.values.insert(key.name, (key.check(value)?, origin));
+            }
+            None => self.unknown.push((name.to_string(), origin)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&(Value, Origin)> {
+        self.values.get(name)
+    }
+
+    /// Value of a string setting
+    pub fn str(&self, name: &str) -> &str {
+        self.get(name)
+            .and_then(|(value, _)| value.as_str())
+            .unwrap_or_default()
+    }
+
+    /// PR settings as used when creating and updating PRs
+    pub fn pr_settings(&self) -> Result<PrSettings> {
+        let fields: serde_json::Map<String, Value> = self
+            .values
+            .iter()
+            .filter_map(|(name, (value, _))| {
+                let field = name.strip_prefix("pr.")?.replace('-', "_");
+                // An empty milestone means none
+                let value = match value {
+                    Value::String(s) if s.is_empty() => Value::Null,
+                    value => value.clone(),
+                };
+                Some((field, value))
+            })
+            .collect();
+        serde_json::from_value(Value::Object(fields)).context("Invalid PR settings")
+    }
+
+    /// Whether a branch follows the `branch-prefix` naming convention
+    pub fn follows_branch_prefix(&self, branch: &str) -> bool {
+        branch.starts_with(self.str("branch-prefix"))
+    }
+}
+
+/// Write a setting to the global or the repository's settings file, keeping
+/// the rest of the file's formatting. `None` removes it.
+pub fn write_file(scope: Scope, name: &str, value: Option<&Value>) -> Result<PathBuf> {
+    let path = scope_path(scope)?.context("Setting is not stored in a file")?;
+    let mut document = read_document(&path)?.unwrap_or_default();
+
+    let (table, leaf) = match name.split_once('.') {
+        Some((table, leaf)) => (Some(table), leaf),
+        None => (None, name),
+    };
+    let parent = match table {
+        Some(table) => {
+            if !document.contains_key(table) {
+                document.insert(table, toml_edit::table());
+            }
+            document[table]
+                .as_table_like_mut()
+                .with_context(|| format!("`{}` in {} is not a table", table, path.display()))?
+        }
+        None => document.as_table_mut() as &mut dyn toml_edit::TableLike,
+    };
+    match value {
+        Some(value) => {
+            parent.insert(leaf, to_toml(value));
+        }
+        None => {
+            parent.remove(leaf);
+        }
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, document.to_string())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
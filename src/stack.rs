@@ -1,55 +1,237 @@
 use crate::git;
+use crate::settings::Settings;
+use crate::storage::{self, Storage};
 use anyhow::{Context, Result};
+use colored::Colorize;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const CONFIG_FILE: &str = "gh-flow.json";
+/// Config early versions kept at the repository root
+const LEGACY_CONFIG_FILE: &str = ".gh-flow.json";
 const PR_TEMPLATE_FILE: &str = "pr-template.md";
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+/// Layout version of `gh-flow.json` written by this gh-flow. Files without a
+/// `version` field are version 1.
+pub const CONFIG_VERSION: u32 = 3;
+
+/// Steps upgrading a saved config, the first one from version 1 to 2
+const MIGRATIONS: &[fn(&mut serde_json::Value)] = &[
+    // 1 → 2: adds `version`; every other field added so far has a default
+    |_| {},
+    // 2 → 3: the `pr` section becomes overrides of the layered settings
+    |value| {
+        let Some(pr) = value.as_object_mut().and_then(|config| config.remove("pr")) else {
+            return;
+        };
+        let defaults = serde_json::to_value(PrSettings::default()).unwrap_or_default();
+        let settings: serde_json::Map<String, serde_json::Value> = pr
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(field, v)| defaults.get(field.as_str()) != Some(*v))
+            .map(|(field, v)| (format!("pr.{}", field.replace('_', "-")), v.clone()))
+            .collect();
+        if !settings.is_empty() {
+            value["settings"] = serde_json::Value::Object(settings);
+        }
+    },
+];
+
+/// `StackConfig` as written to disk, tagged with its layout version
+#[derive(Serialize)]
+struct VersionedConfig<'a> {
+    version: u32,
+    #[serde(flatten)]
+    config: &'a StackConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
 pub struct StackConfig {
     pub base_branch: String,
     pub branches: Vec<BranchInfo>,
+    /// This user's overrides of the team settings, by key (`gh flow config set`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub settings: BTreeMap<String, serde_json::Value>,
+    /// Effective PR settings, resolved from every settings layer on load
+    #[serde(skip)]
+    pub pr: PrSettings,
+}
+
+/// Stack-level settings applied when creating and updating PRs
+#[derive(Debug, Serialize, Deserialize, Default, Clone, PartialEq)]
+pub struct PrSettings {
+    #[serde(default)]
+    pub title_strategy: TitleStrategy,
+    /// Prefix titles with the PR's position in the stack, e.g. `[2/5]`
+    #[serde(default)]
+    pub title_position_prefix: bool,
+    #[serde(default)]
+    pub reviewers: Vec<String>,
+    /// Teams as `team` or `org/team`
+    #[serde(default)]
+    pub team_reviewers: Vec<String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Logins, or `@me` for the authenticated user
+    #[serde(default)]
+    pub assignees: Vec<String>,
+    #[serde(default)]
+    pub milestone: Option<String>,
+    /// Request reviews from the CODEOWNERS of each branch's changed files
+    #[serde(default)]
+    pub codeowners: bool,
+    #[serde(default)]
+    pub draft_policy: DraftPolicy,
+    /// Keep the stack table in a sticky PR comment instead of the description
+    #[serde(default)]
+    pub stack_comment: bool,
+}
+
+/// Which PRs of the stack are kept as drafts
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DraftPolicy {
+    /// Drafts are only changed by `gh flow ready`
+    #[default]
+    Manual,
+    /// Only the bottom-most open PR is ready for review; the next one is
+    /// marked ready when it merges
+    BottomReady,
+}
+
+/// How PR titles are derived from a branch
+#[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TitleStrategy {
+    /// Subject of the first commit in `parent..branch`
+    FirstCommit,
+    /// Subject of the branch's tip commit
+    #[default]
+    LastCommit,
+    /// Branch name with the prefix stripped and dashes turned into spaces
+    BranchName,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct BranchInfo {
     pub name: String,
     pub parent: String,
     pub pr_number: Option<u32>,
+    /// Commit gh-flow last pushed for this branch, used as the force-with-lease expectation
+    #[serde(default)]
+    pub pushed_sha: Option<String>,
 }
 
-/// Get repository identifier (owner/repo) from git remote
-pub fn get_repo_identifier() -> Result<String> {
-    let remote_url = git::run(&["remote", "get-url", "origin"])
-        .context("Failed to get remote URL. Is this a git repository with an origin remote?")?;
+/// Host of github.com repositories
+pub const DEFAULT_HOST: &str = "github.com";
 
-    // Parse owner/repo from various URL formats:
-    // https://github.com/owner/repo.git
-    // git@github.com:owner/repo.git
-    // https://github.com/owner/repo
-    let url = remote_url.trim();
+/// Repository a git remote points to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRepo {
+    /// Web host, e.g. `github.com` or a GitHub Enterprise Server hostname
+    pub host: String,
+    pub owner: String,
+    pub name: String,
+}
 
-    let path = if url.contains("github.com:") {
-        // SSH format: git@github.com:owner/repo.git
-        url.split("github.com:").last().unwrap_or("")
-    } else if url.contains("github.com/") {
-        // HTTPS format: https://github.com/owner/repo.git
-        url.split("github.com/").last().unwrap_or("")
-    } else {
-        // Try to extract from any URL
-        url.rsplit('/').take(2).collect::<Vec<_>>().into_iter().rev().collect::<Vec<_>>().join("/").as_str().to_string().leak()
-    };
+impl RemoteRepo {
+    /// Parse a remote URL. Understands scp-like ssh (`git@host:owner/repo.git`),
+    /// `ssh://` URLs with ports and http(s) URLs on any host.
+    pub fn parse(url: &str) -> Result<Self> {
+        let url = url.trim();
+
+        let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+            let (authority, path) = rest.split_once('/').unwrap_or((rest, ""));
+            let host = authority.rsplit('@').next().unwrap_or(authority);
+            // gh addresses hosts by name only, whatever port git talks to
+            (host.split(':').next().unwrap_or(host), path)
+        } else if let Some((authority, path)) = url.split_once(':') {
+            (authority.rsplit('@').next().unwrap_or(authority), path)
+        } else {
+            anyhow::bail!(
+                "Could not parse repository identifier from remote URL: {}",
+                url
+            );
+        };
 
-    let repo_id = path.trim_end_matches(".git").to_string();
+        // Hosts serving repositories below a prefix put owner/repo last
+        let segments: Vec<&str> = path
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        match segments.as_slice() {
+            [.., owner, name] if !host.is_empty() => Ok(Self {
+                host: host.to_lowercase(),
+                owner: owner.to_string(),
+                name: name.to_string(),
+            }),
+            _ => anyhow::bail!(
+                "Could not parse repository identifier from remote URL: {}",
+                url
+            ),
+        }
+    }
 
-    if repo_id.is_empty() || !repo_id.contains('/') {
-        anyhow::bail!("Could not parse repository identifier from remote URL: {}", url);
+    /// Whether git reaches the remote over ssh, where the host may be an alias
+    fn is_ssh_url(url: &str) -> bool {
+        match url.trim().split_once("://") {
+            Some((scheme, _)) => matches!(scheme, "ssh" | "git+ssh"),
+            None => url.contains(':'),
+        }
     }
 
-    Ok(repo_id)
+    /// `owner/repo`
+    pub fn id(&self) -> String {
+        format!("{}/{}", self.owner, self.name)
+    }
+
+    pub fn is_github_com(&self) -> bool {
+        self.host == DEFAULT_HOST
+    }
+}
+
+/// Get the repository PRs are opened against
+pub fn get_repo() -> Result<RemoteRepo> {
+    get_remote_repo(&git::upstream_remote())
+}
+
+/// Get the repository a git remote points to
+pub fn get_remote_repo(remote: &str) -> Result<RemoteRepo> {
+    let remote_url = git::run(&["remote", "get-url", remote]).with_context(|| {
+        format!(
+            "Failed to get remote URL. Is this a git repository with a '{}' remote?",
+            remote
+        )
+    })?;
+
+    let mut repo = RemoteRepo::parse(&remote_url)?;
+    if RemoteRepo::is_ssh_url(&remote_url) {
+        repo.host = resolve_ssh_host(&repo.host);
+    }
+    Ok(repo)
+}
+
+/// Real hostname of an ssh host alias (`Host github-work` in ~/.ssh/config),
+/// or the host itself when ssh can't tell
+fn resolve_ssh_host(host: &str) -> String {
+    let Ok(output) = std::process::Command::new("ssh")
+        .args(["-G", host])
+        .output()
+    else {
+        return host.to_string();
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("hostname "))
+        .map(|hostname| hostname.trim().to_lowercase())
+        .filter(|hostname| output.status.success() && !hostname.is_empty())
+        .unwrap_or_else(|| host.to_string())
 }
 
 /// Get global config directory (~/.config/gh-flow/)
@@ -65,11 +247,19 @@ pub fn get_global_config_dir() -> Result<PathBuf> {
     Ok(config_home.join("gh-flow"))
 }
 
-/// Get repository-specific config directory (~/.config/gh-flow/repos/owner/repo/)
+/// Get repository-specific config directory (~/.config/gh-flow/repos/owner/repo/,
+/// or ~/.config/gh-flow/repos/host/owner/repo/ for hosts other than github.com).
+/// Keyed on `origin` rather than the configured remotes, so switching to a fork
+/// workflow with `gh flow init --upstream` keeps finding the saved stack.
 pub fn get_repo_config_dir() -> Result<PathBuf> {
-    let global_dir = get_global_config_dir()?;
-    let repo_id = get_repo_identifier()?;
-    Ok(global_dir.join("repos").join(repo_id))
+    let repos_dir = get_global_config_dir()?.join("repos");
+    let repo = get_remote_repo("origin").or_else(|_| get_remote_repo(&git::push_remote()))?;
+    let host_dir = if repo.is_github_com() {
+        repos_dir
+    } else {
+        repos_dir.join(&repo.host)
+    };
+    Ok(host_dir.join(&repo.owner).join(&repo.name))
 }
 
 /// Get PR template path (repo-specific or global)
@@ -102,21 +292,101 @@ pub fn load_pr_template() -> Option<String> {
 }
 
 impl StackConfig {
-    /// Load configuration from file
+    /// Load configuration from the stack ref or the config file, with the
+    /// effective settings resolved
     pub fn load() -> Result<Self> {
+        let mut config = Self::load_saved()?;
+        config.pr = config.settings()?.pr_settings()?;
+        Ok(config)
+    }
+
+    fn load_saved() -> Result<Self> {
+        if Storage::current() == Storage::Refs {
+            if let Some(config) = storage::load()? {
+                return Ok(config);
+            }
+        }
+
         let path = Self::config_path()?;
 
         if !path.exists() {
-            return Ok(Self::default());
+            return Self::load_legacy();
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let (config, migrated) = Self::from_json(&content).with_context(|| {
+            format!(
+                "{} is not a valid gh-flow configuration. Fix it, or run `gh flow init` to rediscover the stack",
+                path.display()
+            )
+        })?;
+        if migrated {
+            config.save()?;
         }
+        Ok(config)
+    }
+
+    /// Move a `.gh-flow.json` left at the repository root by early versions
+    /// into the config directory
+    fn load_legacy() -> Result<Self> {
+        let Ok(root) = git::run(&["rev-parse", "--show-toplevel"]) else {
+            return Ok(Self::default());
+        };
+        let legacy = Path::new(&root).join(LEGACY_CONFIG_FILE);
+        let Ok(content) = fs::read_to_string(&legacy) else {
+            return Ok(Self::default());
+        };
 
-        let content = fs::read_to_string(path)?;
-        let config: Self = serde_json::from_str(&content)?;
+        let (config, _) = Self::from_json(&content).with_context(|| {
+            format!("{} is not a valid gh-flow configuration", legacy.display())
+        })?;
+        config.save()?;
+        println!(
+            "{} Migrated {} to {}; the old file can be deleted",
+            "✓".green(),
+            legacy.display(),
+            Self::config_path()?.display()
+        );
         Ok(config)
     }
 
-    /// Auto-discover branch chain from git history
-    /// This is the primary method - always gets fresh state
+    /// Parse a saved configuration, upgrading older layouts.
+    /// Also returns whether the layout was upgraded.
+    pub fn from_json(content: &str) -> Result<(Self, bool)> {
+        let mut value: serde_json::Value = serde_json::from_str(content)?;
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(1)
+            .max(1) as u32;
+
+        if version > CONFIG_VERSION {
+            anyhow::bail!(
+                "Configuration version {} was written by a newer gh-flow (this one reads up to version {}). Upgrade gh-flow.",
+                version,
+                CONFIG_VERSION
+            );
+        }
+
+        for migration in &MIGRATIONS[(version - 1) as usize..] {
+            migration(&mut value);
+        }
+
+        let config = serde_json::from_value(value)?;
+        Ok((config, version < CONFIG_VERSION))
+    }
+
+    /// Serialize the configuration, tagged with the current layout version
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&VersionedConfig {
+            version: CONFIG_VERSION,
+            config: self,
+        })?)
+    }
+
+    /// Auto-discover branch chain from git history.
+    /// Only the chain is filled in; see [`StackConfig::keep_saved_state`].
     pub fn discover(base_branch: &str) -> Result<Self> {
         let current = git::current_branch()?;
 
@@ -131,7 +401,7 @@ impl StackConfig {
         if log_output.is_empty() {
             let mut config = Self {
                 base_branch: base_branch.to_string(),
-                branches: Vec::new(),
+                ..Default::default()
             };
             if current != base_branch {
                 config.add_branch(current, base_branch.to_string());
@@ -195,7 +465,7 @@ impl StackConfig {
         // Build config
         let mut config = Self {
             base_branch: base_branch.to_string(),
-            branches: Vec::new(),
+            ..Default::default()
         };
 
         let mut prev = base_branch.to_string();
@@ -207,8 +477,80 @@ impl StackConfig {
         Ok(config)
     }
 
-    /// Save configuration to file
+    /// Carry settings, known PR numbers and pushed commits over from the saved
+    /// config, so re-discovering the stack doesn't lose them
+    pub fn keep_saved_state(&mut self, saved: &StackConfig) {
+        for branch in &mut self.branches {
+            if let Some(saved_branch) = saved.get_branch(&branch.name) {
+                branch.pr_number = saved_branch.pr_number;
+                branch.pushed_sha = saved_branch.pushed_sha.clone();
+            }
+        }
+        self.settings = saved.settings.clone();
+        self.pr = saved.pr.clone();
+    }
+
+    /// Track `chain` alongside the other stacks in this config. Its branches
+    /// take the parents in `chain` and keep PR numbers and pushed commits it
+    /// doesn't know; branches of other stacks are left alone.
+    pub fn track(&mut self, chain: &StackConfig) {
+        if self.base_branch.is_empty() {
+            self.base_branch = chain.base_branch.clone();
+        }
+
+        // The chain goes where its first branch was, so every branch still
+        // comes after its parent
+        let at = self
+            .branches
+            .iter()
+            .position(|b| chain.get_branch(&b.name).is_some())
+            .unwrap_or(self.branches.len());
+        let tracked: Vec<BranchInfo> = chain
+            .branches
+            .iter()
+            .map(|branch| {
+                let mut branch = branch.clone();
+                if let Some(saved) = self.get_branch(&branch.name) {
+                    branch.pr_number = branch.pr_number.or(saved.pr_number);
+                    branch.pushed_sha = branch.pushed_sha.or_else(|| saved.pushed_sha.clone());
+                }
+                branch
+            })
+            .collect();
+        self.branches
+            .retain(|b| chain.get_branch(&b.name).is_none());
+        self.branches.splice(at..at, tracked);
+    }
+
+    /// Settings resolved from every layer, with this config's overrides
+    pub fn settings(&self) -> Result<Settings> {
+        Settings::resolve(&self.settings)
+    }
+
+    /// Set or, with `None`, remove this user's override of a setting
+    pub fn set_setting(&mut self, name: &str, value: Option<serde_json::Value>) -> Result<()> {
+        match value {
+            Some(value) => self.settings.insert(name.to_string(), value),
+            None => self.settings.remove(name),
+        };
+        self.pr = self.settings()?.pr_settings()?;
+        Ok(())
+    }
+
+    /// Branch the stack is based on, or the `base-branch` setting before `init`
+    pub fn base(&self) -> Result<String> {
+        if !self.base_branch.is_empty() {
+            return Ok(self.base_branch.clone());
+        }
+        Ok(self.settings()?.str("base-branch").to_string())
+    }
+
+    /// Save configuration to the stack ref or the config file
     pub fn save(&self) -> Result<()> {
+        if Storage::current() == Storage::Refs {
+            return storage::save(self);
+        }
+
         let path = Self::config_path()?;
 
         // Ensure parent directory exists
@@ -216,13 +558,17 @@ impl StackConfig {
             fs::create_dir_all(parent)?;
         }
 
-        let content = serde_json::to_string_pretty(self)?;
-        fs::write(path, content)?;
+        // Write a temp file and rename it over the config, so an interrupted
+        // save never leaves a truncated file behind
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, self.to_json()?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to replace {}", path.display()))?;
         Ok(())
     }
 
     /// Get config file path (~/.config/gh-flow/repos/owner/repo/gh-flow.json)
-    fn config_path() -> Result<PathBuf> {
+    pub fn config_path() -> Result<PathBuf> {
         let repo_dir = get_repo_config_dir()?;
         Ok(repo_dir.join(CONFIG_FILE))
     }
@@ -233,18 +579,387 @@ impl StackConfig {
             name,
             parent,
             pr_number: None,
+            pushed_sha: None,
         });
     }
 
+    /// Remove a branch from the stack, moving the branches based on it onto
+    /// its parent. Returns the names of the moved branches.
+    pub fn remove_branch(&mut self, name: &str) -> Vec<String> {
+        let Some(index) = self.branches.iter().position(|b| b.name == name) else {
+            return Vec::new();
+        };
+        let removed = self.branches.remove(index);
+
+        let mut moved = Vec::new();
+        for branch in &mut self.branches {
+            if branch.parent == removed.name {
+                branch.parent = removed.parent.clone();
+                moved.push(branch.name.clone());
+            }
+        }
+        moved
+    }
+
     /// Get branch info
-    #[allow(dead_code)]
     pub fn get_branch(&self, name: &str) -> Option<&BranchInfo> {
         self.branches.iter().find(|b| b.name == name)
     }
 
     /// Get branch info mutably
-    #[allow(dead_code)]
     pub fn get_branch_mut(&mut self, name: &str) -> Option<&mut BranchInfo> {
         self.branches.iter_mut().find(|b| b.name == name)
     }
+
+    /// The chain through `branch`, bottom first: its tracked ancestors down to
+    /// the base, then its first tracked child at each level above it
+    pub fn chain_through(&self, branch: &str) -> Vec<BranchInfo> {
+        let mut chain: Vec<BranchInfo> = Vec::new();
+        let mut name = branch;
+        while let Some(info) = self.get_branch(name) {
+            if chain.iter().any(|b| b.name == info.name) {
+                break;
+            }
+            chain.insert(0, info.clone());
+            name = &info.parent;
+        }
+
+        let mut top = branch.to_string();
+        while let Some(child) = self
+            .branches
+            .iter()
+            .find(|b| b.parent == top && !chain.iter().any(|c| c.name == b.name))
+        {
+            chain.push(child.clone());
+            top = child.name.clone();
+        }
+        chain
+    }
+
+    /// Branches at the bottom of a tracked stack: based on the base branch or
+    /// on a branch the stack doesn't track
+    pub fn roots(&self) -> Vec<&BranchInfo> {
+        self.branches
+            .iter()
+            .filter(|b| self.get_branch(&b.parent).is_none())
+            .collect()
+    }
+
+    /// Tracked stacks: each root with everything above it, bottom first
+    pub fn stacks(&self) -> Vec<Vec<BranchInfo>> {
+        self.roots()
+            .into_iter()
+            .map(|root| {
+                let mut stack = vec![root.clone()];
+                let mut i = 0;
+                while i < stack.len() {
+                    let name = stack[i].name.clone();
+                    stack.extend(self.branches.iter().filter(|b| b.parent == name).cloned());
+                    i += 1;
+                }
+                stack
+            })
+            .collect()
+    }
+
+    /// The stack `current` is on, with this config's settings: the chain
+    /// through it, or the only tracked stack when it is on none
+    pub fn stack_for(&self, current: &str) -> Result<StackConfig> {
+        let branches = if self.get_branch(current).is_some() {
+            self.chain_through(current)
+        } else {
+            match self.roots().as_slice() {
+                [] => Vec::new(),
+                [root] => self.chain_through(&root.name),
+                roots => anyhow::bail!(
+                    "{} is not on a tracked stack. Check out a branch of the stack to work on (bottom branches: {})",
+                    current,
+                    roots.iter().map(|b| b.name.as_str()).collect::<Vec<_>>().join(", ")
+                ),
+            }
+        };
+        Ok(StackConfig {
+            base_branch: self.base_branch.clone(),
+            branches,
+            settings: self.settings.clone(),
+            pr: self.pr.clone(),
+        })
+    }
+
+    /// The stack HEAD is on; see [`StackConfig::stack_for`]
+    pub fn current_stack(&self) -> Result<StackConfig> {
+        self.stack_for(&git::current_branch().unwrap_or_default())
+    }
+
+    /// Put a stack taken with [`StackConfig::stack_for`] back, with the
+    /// settings changed on it, and save
+    pub fn save_stack(&mut self, stack: &StackConfig) -> Result<()> {
+        self.track(stack);
+        self.settings = stack.settings.clone();
+        self.pr = stack.pr.clone();
+        self.save()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(branches: &[(&str, &str)]) -> StackConfig {
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        for (name, parent) in branches {
+            config.add_branch(name.to_string(), parent.to_string());
+        }
+        config
+    }
+
+    #[test]
+    fn every_older_version_has_a_migration() {
+        assert_eq!(MIGRATIONS.len(), CONFIG_VERSION as usize - 1);
+    }
+
+    #[test]
+    fn migrates_version_1_through_3() {
+        let v1 = r#"{
+            "base_branch": "main",
+            "branches": [{ "name": "a", "parent": "main", "pr_number": 4 }],
+            "pr": { "title_strategy": "first-commit", "title_position_prefix": false, "labels": ["x"] }
+        }"#;
+        let (config, migrated) = StackConfig::from_json(v1).unwrap();
+        assert!(migrated);
+        assert_eq!(config.branches[0].pr_number, Some(4));
+        assert_eq!(config.branches[0].pushed_sha, None);
+        // Only values that differ from the defaults become overrides
+        let expected: BTreeMap<String, serde_json::Value> = [
+            (
+                "pr.title-strategy".to_string(),
+                serde_json::json!("first-commit"),
+            ),
+            ("pr.labels".to_string(), serde_json::json!(["x"])),
+        ]
+        .into();
+        assert_eq!(config.settings, expected);
+    }
+
+    #[test]
+    fn migrates_version_2_without_pr_section() {
+        let v2 = r#"{ "version": 2, "base_branch": "main", "branches": [] }"#;
+        let (config, migrated) = StackConfig::from_json(v2).unwrap();
+        assert!(migrated);
+        assert!(config.settings.is_empty());
+    }
+
+    #[test]
+    fn current_version_is_not_migrated() {
+        let mut config = StackConfig {
+            base_branch: "main".to_string(),
+            ..Default::default()
+        };
+        config.add_branch("a".to_string(), "main".to_string());
+        config
+            .settings
+            .insert("pr.labels".to_string(), serde_json::json!(["x"]));
+
+        let (loaded, migrated) = StackConfig::from_json(&config.to_json().unwrap()).unwrap();
+        assert!(!migrated);
+        assert_eq!(loaded, config);
+    }
+
+    #[test]
+    fn refuses_configs_from_newer_versions() {
+        let newer = format!(
+            r#"{{ "version": {}, "base_branch": "main", "branches": [] }}"#,
+            CONFIG_VERSION + 1
+        );
+        let error = StackConfig::from_json(&newer).unwrap_err();
+        assert!(error.to_string().contains("newer gh-flow"));
+    }
+
+    #[test]
+    fn parse_remote_urls() {
+        let cases = [
+            ("git@github.com:o/r.git", "github.com"),
+            ("https://github.com/o/r", "github.com"),
+            ("ssh://git@ghe.example.com:2222/o/r.git", "ghe.example.com"),
+            ("https://ghe.example.com:8443/o/r.git", "ghe.example.com"),
+            (
+                "https://user@GHE.example.com/prefix/o/r/",
+                "ghe.example.com",
+            ),
+            ("git@github-work:o/r.git", "github-work"),
+        ];
+        for (url, host) in cases {
+            let repo = RemoteRepo::parse(url).unwrap();
+            assert_eq!(
+                (repo.host.as_str(), repo.id().as_str()),
+                (host, "o/r"),
+                "{}",
+                url
+            );
+        }
+        assert!(RemoteRepo::parse("/just/a/path").is_err());
+    }
+
+    #[test]
+    fn ssh_urls_are_recognized() {
+        assert!(RemoteRepo::is_ssh_url("git@github-work:o/r.git"));
+        assert!(RemoteRepo::is_ssh_url("ssh://git@host/o/r"));
+        assert!(!RemoteRepo::is_ssh_url("https://host/o/r"));
+        assert!(!RemoteRepo::is_ssh_url("file://localhost/tmp/x/y.git"));
+    }
+
+    #[test]
+    fn keep_saved_state_carries_prs_pushed_commits_and_settings() {
+        let mut saved = stack(&[("a", "main"), ("b", "a")]);
+        saved.branches[0].pr_number = Some(1);
+        saved.branches[0].pushed_sha = Some("abc".to_string());
+        saved.branches[1].pr_number = Some(2);
+        saved
+            .settings
+            .insert("pr.labels".to_string(), serde_json::json!(["x"]));
+        saved.pr.labels = vec!["x".to_string()];
+
+        // b was dropped from the chain and c is new
+        let mut discovered = stack(&[("a", "main"), ("c", "a")]);
+        discovered.keep_saved_state(&saved);
+
+        assert_eq!(discovered.branches[0].pr_number, Some(1));
+        assert_eq!(discovered.branches[0].pushed_sha.as_deref(), Some("abc"));
+        assert_eq!(discovered.branches[1].pr_number, None);
+        assert_eq!(discovered.settings, saved.settings);
+        assert_eq!(discovered.pr.labels, vec!["x"]);
+    }
+
+    #[test]
+    fn keep_saved_state_keeps_the_discovered_chain() {
+        let saved = stack(&[("b", "main"), ("a", "b")]);
+        let mut discovered = stack(&[("a", "main"), ("b", "a")]);
+        discovered.keep_saved_state(&saved);
+
+        let chain: Vec<(&str, &str)> = discovered
+            .branches
+            .iter()
+            .map(|b| (b.name.as_str(), b.parent.as_str()))
+            .collect();
+        assert_eq!(chain, vec![("a", "main"), ("b", "a")]);
+    }
+
+    fn names(config: &StackConfig) -> Vec<(&str, &str)> {
+        config
+            .branches
+            .iter()
+            .map(|b| (b.name.as_str(), b.parent.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn track_keeps_the_other_stacks() {
+        let mut saved = stack(&[("a1", "main"), ("a2", "a1"), ("b1", "main"), ("b2", "b1")]);
+        saved.branches[2].pr_number = Some(3);
+
+        // HEAD's chain goes through b3; its sibling b2 stays tracked
+        let mut chain = stack(&[("b1", "main"), ("b3", "b1")]);
+        chain.branches[1].pr_number = Some(5);
+        saved.track(&chain);
+
+        assert_eq!(
+            names(&saved),
+            vec![
+                ("a1", "main"),
+                ("a2", "a1"),
+                ("b1", "main"),
+                ("b3", "b1"),
+                ("b2", "b1")
+            ]
+        );
+        assert_eq!(saved.get_branch("b1").unwrap().pr_number, Some(3));
+        assert_eq!(saved.get_branch("b3").unwrap().pr_number, Some(5));
+    }
+
+    #[test]
+    fn track_reorders_a_restacked_chain() {
+        let mut saved = stack(&[("x", "main"), ("a", "main"), ("b", "a")]);
+        saved.track(&stack(&[("b", "main"), ("a", "b")]));
+        assert_eq!(
+            names(&saved),
+            vec![("x", "main"), ("b", "main"), ("a", "b")]
+        );
+    }
+
+    #[test]
+    fn track_adds_a_new_stack_and_fills_in_the_base() {
+        let mut saved = StackConfig::default();
+        saved.track(&stack(&[("a", "main")]));
+        saved.track(&stack(&[("b", "main")]));
+        assert_eq!(saved.base_branch, "main");
+        assert_eq!(names(&saved), vec![("a", "main"), ("b", "main")]);
+    }
+
+    #[test]
+    fn chain_through_follows_parents_down_and_first_children_up() {
+        let config = stack(&[
+            ("a1", "main"),
+            ("b1", "main"),
+            ("a2", "a1"),
+            ("a3", "a2"),
+            ("x", "a1"),
+        ]);
+        let chain = |branch| {
+            config
+                .chain_through(branch)
+                .into_iter()
+                .map(|b| b.name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(chain("a2"), vec!["a1", "a2", "a3"]);
+        assert_eq!(chain("a1"), vec!["a1", "a2", "a3"]);
+        assert_eq!(chain("x"), vec!["a1", "x"]);
+        assert_eq!(chain("b1"), vec!["b1"]);
+    }
+
+    #[test]
+    fn stack_for_picks_the_chain_of_the_current_branch() {
+        let mut config = stack(&[("a1", "main"), ("b1", "main"), ("a2", "a1")]);
+        config
+            .settings
+            .insert("pr.labels".to_string(), serde_json::json!(["x"]));
+
+        let current = config.stack_for("a2").unwrap();
+        assert_eq!(names(&current), vec![("a1", "main"), ("a2", "a1")]);
+        assert_eq!(current.settings, config.settings);
+
+        let error = config.stack_for("main").unwrap_err();
+        assert!(error.to_string().contains("a1, b1"), "{}", error);
+    }
+
+    #[test]
+    fn stack_for_uses_the_only_stack_off_it() {
+        let config = stack(&[("a1", "main"), ("a2", "a1")]);
+        assert_eq!(names(&config.stack_for("main").unwrap()), names(&config));
+        assert!(StackConfig::default()
+            .stack_for("main")
+            .unwrap()
+            .branches
+            .is_empty());
+    }
+
+    #[test]
+    fn stacks_splits_the_config_at_each_root() {
+        let config = stack(&[
+            ("a1", "main"),
+            ("b1", "main"),
+            ("a2", "a1"),
+            ("b2", "b1"),
+            ("a3", "a1"),
+        ]);
+        let stacks: Vec<Vec<String>> = config
+            .stacks()
+            .into_iter()
+            .map(|stack| stack.into_iter().map(|b| b.name).collect())
+            .collect();
+        assert_eq!(stacks, vec![vec!["a1", "a2", "a3"], vec!["b1", "b2"]]);
+    }
 }
@@ -0,0 +1,652 @@
+use crate::git;
+use crate::stack::{self, BranchInfo, StackConfig};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Namespace of the shared stack metadata: one ref per stack, `<STACKS_REF><id>`
+pub const STACKS_REF: &str = "refs/gh-flow/stacks/";
+/// Where the remote's copies are fetched to before merging them into ours
+const REMOTE_STACKS_REF: &str = "refs/gh-flow/remote/stacks/";
+const STACK_FILE: &str = "gh-flow.json";
+const STORAGE_KEY: &str = "gh-flow.storage";
+/// State kept next to the shared stack that only applies to this machine
+const LOCAL_STATE_FILE: &str = "local.json";
+
+/// Where stack metadata is stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Storage {
+    /// ~/.config/gh-flow/repos/<owner>/<repo>/gh-flow.json, private to this machine
+    File,
+    /// A commit under refs/gh-flow/stacks/, pushed and fetched with the branches
+    /// so teammates see the same stack
+    Refs,
+}
+
+impl Storage {
+    /// Backend configured for this repository (`git config gh-flow.storage`)
+    pub fn current() -> Self {
+        match git::config_get(STORAGE_KEY).as_deref() {
+            Some("refs") => Storage::Refs,
+            _ => Storage::File,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Storage::File => "file",
+            Storage::Refs => "refs",
+        }
+    }
+
+    pub fn set(self) -> Result<()> {
+        git::run(&["config", STORAGE_KEY, self.name()])?;
+        Ok(())
+    }
+}
+
+/// What fetching the shared stack did to our copy
+#[derive(Debug, PartialEq, Eq)]
+pub enum PullOutcome {
+    /// The remote has no shared stack
+    Missing,
+    UpToDate,
+    FastForwarded,
+    /// Both sides changed the stack; the edits were merged
+    Merged,
+}
+
+/// Parts of the stack that must not be shared: what this machine last pushed
+/// is its force-with-lease expectation, and a teammate's would be wrong; the
+/// user's setting overrides are theirs alone
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct LocalState {
+    /// Commit last pushed, by branch
+    #[serde(default)]
+    pushed: BTreeMap<String, String>,
+    /// `gh flow config set` overrides, by key
+    #[serde(default)]
+    settings: BTreeMap<String, serde_json::Value>,
+}
+
+impl LocalState {
+    fn path() -> Result<PathBuf> {
+        Ok(stack::get_repo_config_dir()?.join(LOCAL_STATE_FILE))
+    }
+
+    fn load() -> Result<Self> {
+        let path = Self::path()?;
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Ok(Self::default());
+        };
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &path).with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Take the local parts out of `config`, leaving what is shared
+    fn split(config: &StackConfig) -> (StackConfig, Self) {
+        let mut shared = config.clone();
+        let mut local = Self {
+            settings: std::mem::take(&mut shared.settings),
+            ..Default::default()
+        };
+        for branch in &mut shared.branches {
+            if let Some(sha) = branch.pushed_sha.take() {
+                local.pushed.insert(branch.name.clone(), sha);
+            }
+        }
+        (shared, local)
+    }
+
+    /// Put the local parts back into a shared config
+    fn apply(&self, config: &mut StackConfig) {
+        config.settings = self.settings.clone();
+        for branch in &mut config.branches {
+            branch.pushed_sha = self.pushed.get(&branch.name).cloned();
+        }
+    }
+}
+
+/// Stack stored at `rev`, as it was written
+fn read_raw(rev: &str) -> Result<Option<StackConfig>> {
+    if git::rev_parse(rev).is_none() {
+        return Ok(None);
+    }
+    let content = git::run(&["show", &format!("{}:{}", rev, STACK_FILE)])?;
+    let (config, _) =
+        StackConfig::from_json(&content).context("Failed to parse shared stack metadata")?;
+    Ok(Some(config))
+}
+
+/// Shared stack stored at `rev`. Only this machine's state counts, whatever
+/// older versions shared.
+fn read_at(rev: &str) -> Result<Option<StackConfig>> {
+    Ok(read_raw(rev)?.map(|config| LocalState::split(&config).0))
+}
+
+/// Ids and commits of the stack refs under `prefix`
+fn list_refs(prefix: &str) -> Result<Vec<(String, String)>> {
+    let output = git::run(&["for-each-ref", "--format=%(refname) %(objectname)", prefix])?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let (name, commit) = line.split_once(' ')?;
+            Some((name.strip_prefix(prefix)?.to_string(), commit.to_string()))
+        })
+        .collect())
+}
+
+/// Every stack stored under our stack refs, by id, as written
+fn read_stored() -> Result<BTreeMap<String, StackConfig>> {
+    let mut stored = BTreeMap::new();
+    for (id, commit) in list_refs(STACKS_REF)? {
+        if let Some(config) = read_raw(&commit)? {
+            stored.insert(id, config);
+        }
+    }
+    Ok(stored)
+}
+
+/// One config holding every stored stack
+fn combine(stored: &BTreeMap<String, StackConfig>) -> StackConfig {
+    let mut config = StackConfig::default();
+    for stack in stored.values() {
+        if config.base_branch.is_empty() {
+            config.base_branch = stack.base_branch.clone();
+        }
+        for branch in &stack.branches {
+            // A branch moved between stacks by concurrent edits: the first wins
+            if config.get_branch(&branch.name).is_none() {
+                config.branches.push(branch.clone());
+            }
+        }
+    }
+    config
+}
+
+/// Load every stack from the stack refs, with this machine's state
+pub fn load() -> Result<Option<StackConfig>> {
+    let stored = read_stored()?;
+    if stored.is_empty() {
+        return Ok(None);
+    }
+    let mut config = LocalState::split(&combine(&stored)).0;
+    LocalState::load()?.apply(&mut config);
+    Ok(Some(config))
+}
+
+/// A ref id for a new stack, from its bottom branch
+fn new_id(root: &str, taken: impl Fn(&str) -> bool) -> String {
+    let id: String = root
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    let id = id.trim_matches('-').to_string();
+    let id = if id.is_empty() {
+        "stack".to_string()
+    } else {
+        id
+    };
+    (1..)
+        .map(|n| {
+            if n == 1 {
+                id.clone()
+            } else {
+                format!("{}-{}", id, n)
+            }
+        })
+        .find(|candidate| !taken(candidate))
+        .unwrap_or(id)
+}
+
+/// Split `config` into one config per stack, keyed by ref id. A stack keeps
+/// the id of the stored stack its bottom-most previously stored branch was in;
+/// new stacks are named after their bottom branch. Stored stacks left without
+/// branches map to an empty stack, so removing them is shared like any edit.
+fn assign_ids(
+    config: &StackConfig,
+    stored: &BTreeMap<String, StackConfig>,
+) -> BTreeMap<String, StackConfig> {
+    let mut assigned: BTreeMap<String, StackConfig> = BTreeMap::new();
+    let mut fresh: Vec<Vec<BranchInfo>> = Vec::new();
+
+    for stack in config.stacks() {
+        let id = stack.iter().find_map(|branch| {
+            stored
+                .iter()
+                .find(|(id, old)| {
+                    old.get_branch(&branch.name).is_some() && !assigned.contains_key(*id)
+                })
+                .map(|(id, _)| id.clone())
+        });
+        match id {
+            Some(id) => {
+                assigned.insert(id, stack_config(config, stack));
+            }
+            None => fresh.push(stack),
+        }
+    }
+
+    for stack in fresh {
+        let id = new_id(&stack[0].name, |id| {
+            assigned.contains_key(id) || stored.contains_key(id)
+        });
+        assigned.insert(id, stack_config(config, stack));
+    }
+
+    for id in stored.keys() {
+        assigned
+            .entry(id.clone())
+            .or_insert_with(|| stack_config(config, Vec::new()));
+    }
+    assigned
+}
+
+/// A config holding one stack of `config`
+fn stack_config(config: &StackConfig, branches: Vec<BranchInfo>) -> StackConfig {
+    StackConfig {
+        base_branch: config.base_branch.clone(),
+        branches,
+        ..Default::default()
+    }
+}
+
+fn write_commit(config: &StackConfig, parents: &[&str], message: &str) -> Result<String> {
+    let json = config.to_json()?;
+    let blob = git::run_with_input(&["hash-object", "-w", "--stdin"], &json)?;
+    let tree = git::run_with_input(
+        &["mktree"],
+        &format!("100644 blob {}\t{}\n", blob, STACK_FILE),
+    )?;
+
+    let mut args = vec!["commit-tree", tree.as_str(), "-m", message];
+    for parent in parents {
+        args.extend(["-p", parent]);
+    }
+    git::run(&args)
+}
+
+/// Move a stack ref, failing if someone else moved it since we read `old`
+fn update_ref(name: &str, new: &str, old: Option<&str>) -> Result<()> {
+    git::run(&["update-ref", name, new, old.unwrap_or("")])?;
+    Ok(())
+}
+
+/// Record the shared parts of `config` as new commits on the refs of the
+/// stacks that changed, and this machine's state next to them
+pub fn save(config: &StackConfig) -> Result<()> {
+    let (shared, local) = LocalState::split(config);
+    if LocalState::load().ok().as_ref() != Some(&local) {
+        local.save()?;
+    }
+
+    let stored = read_stored()?;
+    for (id, stack) in assign_ids(&shared, &stored) {
+        if stored.get(&id) == Some(&stack) {
+            continue;
+        }
+        let name = format!("{}{}", STACKS_REF, id);
+        let parent = git::rev_parse(&name);
+        let parents: Vec<&str> = parent.iter().map(String::as_str).collect();
+        let commit = write_commit(&stack, &parents, "Update stack")?;
+        update_ref(&name, &commit, parent.as_deref())?;
+    }
+    Ok(())
+}
+
+/// Fetch the remote's stack metadata and merge every stack into ours
+pub fn pull() -> Result<PullOutcome> {
+    let remote = git::push_remote();
+    let pattern = format!("{}*", STACKS_REF);
+    if git::run(&["ls-remote", "--exit-code", &remote, &pattern]).is_err() {
+        return Ok(PullOutcome::Missing);
+    }
+    let refspec = format!("+{}*:{}*", STACKS_REF, REMOTE_STACKS_REF);
+    git::run(&["fetch", "--prune", &remote, &refspec])?;
+
+    let mut outcome = PullOutcome::UpToDate;
+    for (id, theirs) in list_refs(REMOTE_STACKS_REF)? {
+        let pulled = pull_stack(&format!("{}{}", STACKS_REF, id), &theirs)?;
+        if pulled == PullOutcome::Merged || outcome == PullOutcome::UpToDate {
+            outcome = pulled;
+        }
+    }
+    Ok(outcome)
+}
+
+/// Bring the stack ref `name` up to date with their commit `theirs`
+fn pull_stack(name: &str, theirs: &str) -> Result<PullOutcome> {
+    let Some(ours) = git::rev_parse(name) else {
+        update_ref(name, theirs, None)?;
+        return Ok(PullOutcome::FastForwarded);
+    };
+
+    if ours == theirs || git::is_ancestor(theirs, &ours) {
+        return Ok(PullOutcome::UpToDate);
+    }
+    if git::is_ancestor(&ours, theirs) {
+        update_ref(name, theirs, Some(&ours))?;
+        return Ok(PullOutcome::FastForwarded);
+    }
+
+    let base = git::run(&["merge-base", &ours, theirs])
+        .ok()
+        .and_then(|base| read_at(&base).transpose())
+        .transpose()?
+        .unwrap_or_default();
+    let ours_config = read_at(&ours)?.unwrap_or_default();
+    let theirs_config = read_at(theirs)?.unwrap_or_default();
+
+    let merged = merge(&base, &ours_config, &theirs_config);
+    let commit = write_commit(&merged, &[&ours, theirs], "Merge stack")?;
+    update_ref(name, &commit, Some(&ours))?;
+    Ok(PullOutcome::Merged)
+}
+
+/// Publish our stack metadata, merging in concurrent edits first when needed
+pub fn push() -> Result<()> {
+    if list_refs(STACKS_REF)?.is_empty() {
+        return Ok(());
+    }
+
+    let remote = git::push_remote();
+    let refspec = format!("{0}*:{0}*", STACKS_REF);
+    if git::run(&["push", "--quiet", &remote, &refspec]).is_ok() {
+        return Ok(());
+    }
+
+    // Someone else updated the stack since we last fetched it
+    pull()?;
+    git::run(&["push", "--quiet", &remote, &refspec])
+        .context("Failed to push shared stack metadata")?;
+    Ok(())
+}
+
+/// Take their value if we didn't change ours since `base`
+fn pick<T: PartialEq + Clone>(base: Option<&T>, ours: &T, theirs: &T) -> T {
+    if base == Some(ours) {
+        theirs.clone()
+    } else {
+        ours.clone()
+    }
+}
+
+/// Three-way merge of two edits of the stack. Changes are combined field by
+/// field; when both sides changed the same field, ours wins.
+fn merge(base: &StackConfig, ours: &StackConfig, theirs: &StackConfig) -> StackConfig {
+    let mut branches: Vec<BranchInfo> = Vec::new();
+
+    for branch in &ours.branches {
+        let base_branch = base.get_branch(&branch.name);
+        match theirs.get_branch(&branch.name) {
+            Some(their_branch) => branches.push(BranchInfo {
+                name: branch.name.clone(),
+                parent: pick(
+                    base_branch.map(|b| &b.parent),
+                    &branch.parent,
+                    &their_branch.parent,
+                ),
+                pr_number: pick(
+                    base_branch.map(|b| &b.pr_number),
+                    &branch.pr_number,
+                    &their_branch.pr_number,
+                ),
+                // Machine-local, never part of the shared stack
+                pushed_sha: None,
+            }),
+            // They removed it; keep it only if we changed it since
+            None if base_branch == Some(branch) => {}
+            None => branches.push(branch.clone()),
+        }
+    }
+
+    // Branches they added go right after their parent
+    for branch in &theirs.branches {
+        if ours.get_branch(&branch.name).is_some() || base.get_branch(&branch.name).is_some() {
+            continue;
+        }
+        let position = branches
+            .iter()
+            .position(|b| b.name == branch.parent)
+            .map(|i| i + 1)
+            .unwrap_or(branches.len());
+        branches.insert(position, branch.clone());
+    }
+
+    // Settings are machine-local, never part of the shared stack
+    StackConfig {
+        base_branch: pick(
+            Some(&base.base_branch),
+            &ours.base_branch,
+            &theirs.base_branch,
+        ),
+        branches,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(branches: &[(&str, &str, Option<u32>)]) -> StackConfig {
+        StackConfig {
+            base_branch: "main".to_string(),
+            branches: branches
+                .iter()
+                .map(|(name, parent, pr_number)| BranchInfo {
+                    name: name.to_string(),
+                    parent: parent.to_string(),
+                    pr_number: *pr_number,
+                    pushed_sha: None,
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn chain(config: &StackConfig) -> Vec<(&str, &str, Option<u32>)> {
+        config
+            .branches
+            .iter()
+            .map(|b| (b.name.as_str(), b.parent.as_str(), b.pr_number))
+            .collect()
+    }
+
+    fn ids(assigned: &BTreeMap<String, StackConfig>) -> Vec<(&str, Vec<&str>)> {
+        assigned
+            .iter()
+            .map(|(id, stack)| {
+                let names = stack.branches.iter().map(|b| b.name.as_str()).collect();
+                (id.as_str(), names)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn assign_ids_names_new_stacks_after_their_bottom_branch() {
+        let config = stack(&[
+            ("feat/a1", "main", None),
+            ("feat/a2", "feat/a1", None),
+            ("b1", "main", None),
+        ]);
+
+        let assigned = assign_ids(&config, &BTreeMap::new());
+        assert_eq!(
+            ids(&assigned),
+            vec![("b1", vec!["b1"]), ("feat-a1", vec!["feat/a1", "feat/a2"])]
+        );
+    }
+
+    #[test]
+    fn assign_ids_keeps_the_stored_id_of_a_stack() {
+        let stored = BTreeMap::from([
+            ("default".to_string(), stack(&[("a1", "main", None)])),
+            ("b1".to_string(), stack(&[("b0", "main", None)])),
+        ]);
+        // a1 gained a child and b0 was replaced by b1, which takes a fresh id
+        let config = stack(&[
+            ("a1", "main", None),
+            ("a2", "a1", None),
+            ("b1", "main", None),
+        ]);
+
+        let assigned = assign_ids(&config, &stored);
+        assert_eq!(
+            ids(&assigned),
+            vec![
+                ("b1", vec![]),
+                ("b1-2", vec!["b1"]),
+                ("default", vec!["a1", "a2"])
+            ]
+        );
+    }
+
+    #[test]
+    fn assign_ids_keeps_a_stack_whose_bottom_was_removed() {
+        let stored = BTreeMap::from([(
+            "a1".to_string(),
+            stack(&[("a1", "main", None), ("a2", "a1", None)]),
+        )]);
+        let config = stack(&[("a2", "main", None)]);
+
+        let assigned = assign_ids(&config, &stored);
+        assert_eq!(ids(&assigned), vec![("a1", vec!["a2"])]);
+    }
+
+    #[test]
+    fn combine_joins_the_stored_stacks() {
+        let stored = BTreeMap::from([
+            ("a1".to_string(), stack(&[("a1", "main", None)])),
+            (
+                "b1".to_string(),
+                stack(&[("b1", "main", None), ("b2", "b1", None)]),
+            ),
+            ("old".to_string(), stack(&[])),
+        ]);
+
+        let config = combine(&stored);
+        assert_eq!(config.base_branch, "main");
+        assert_eq!(
+            chain(&config),
+            vec![
+                ("a1", "main", None),
+                ("b1", "main", None),
+                ("b2", "b1", None)
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_combines_edits_of_different_fields() {
+        let base = stack(&[("a", "main", None), ("b", "a", None)]);
+        let ours = stack(&[("a", "main", Some(1)), ("b", "a", None)]);
+        let theirs = stack(&[("a", "main", None), ("b", "a", Some(2))]);
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(
+            chain(&merged),
+            vec![("a", "main", Some(1)), ("b", "a", Some(2))]
+        );
+    }
+
+    #[test]
+    fn merge_prefers_ours_when_both_changed_a_field() {
+        let base = stack(&[("a", "main", None), ("b", "a", None)]);
+        let ours = stack(&[("a", "main", None), ("b", "main", None)]);
+        let theirs = stack(&[("a", "main", None), ("b", "x", None)]);
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(
+            chain(&merged),
+            vec![("a", "main", None), ("b", "main", None)]
+        );
+    }
+
+    #[test]
+    fn merge_drops_branches_they_removed_unless_we_changed_them() {
+        let base = stack(&[("a", "main", None), ("b", "a", None), ("c", "b", None)]);
+        let ours = stack(&[("a", "main", None), ("b", "a", None), ("c", "b", Some(3))]);
+        let theirs = stack(&[("a", "main", None)]);
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(
+            chain(&merged),
+            vec![("a", "main", None), ("c", "b", Some(3))]
+        );
+    }
+
+    #[test]
+    fn merge_inserts_their_branches_after_the_parent() {
+        let base = stack(&[("a", "main", None), ("b", "a", None)]);
+        let ours = stack(&[("a", "main", None), ("b", "a", None), ("c", "b", None)]);
+        let theirs = stack(&[("a", "main", None), ("x", "a", None), ("b", "a", None)]);
+
+        let merged = merge(&base, &ours, &theirs);
+        let names: Vec<&str> = merged.branches.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, vec!["a", "x", "b", "c"]);
+    }
+
+    #[test]
+    fn merge_never_shares_pushed_commits() {
+        let base = stack(&[("a", "main", None)]);
+        let mut ours = base.clone();
+        ours.branches[0].pushed_sha = Some("ours".to_string());
+        let mut theirs = base.clone();
+        theirs.branches[0].pushed_sha = Some("theirs".to_string());
+
+        assert_eq!(merge(&base, &ours, &theirs).branches[0].pushed_sha, None);
+    }
+
+    #[test]
+    fn merge_never_shares_settings() {
+        let base = stack(&[("a", "main", None)]);
+        let mut theirs = base.clone();
+        theirs
+            .settings
+            .insert("merge-method".to_string(), serde_json::json!("rebase"));
+
+        assert!(merge(&base, &base, &theirs).settings.is_empty());
+    }
+
+    #[test]
+    fn local_state_round_trips_pushed_commits_and_settings() {
+        let mut config = stack(&[("a", "main", Some(1)), ("b", "a", None)]);
+        config.branches[0].pushed_sha = Some("abc".to_string());
+        config
+            .settings
+            .insert("merge-method".to_string(), serde_json::json!("rebase"));
+
+        let (mut shared, local) = LocalState::split(&config);
+        assert!(shared.branches.iter().all(|b| b.pushed_sha.is_none()));
+        assert!(shared.settings.is_empty());
+        assert_eq!(local.pushed.get("a").map(String::as_str), Some("abc"));
+        assert_eq!(
+            local.settings.get("merge-method"),
+            Some(&serde_json::json!("rebase"))
+        );
+
+        local.apply(&mut shared);
+        assert_eq!(shared, config);
+    }
+}
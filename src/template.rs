@@ -0,0 +1,423 @@
+use anyhow::{bail, Result};
+use std::collections::{BTreeSet, HashMap};
+
+/// Value bound to a template variable
+#[derive(Debug, Clone)]
+pub enum Value {
+    Str(String),
+    Bool(bool),
+    List(Vec<Context>),
+}
+
+impl Value {
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Str(s) => !s.is_empty(),
+            Value::Bool(b) => *b,
+            Value::List(items) => !items.is_empty(),
+        }
+    }
+
+    fn render(&self) -> String {
+        match self {
+            Value::Str(s) => s.clone(),
+            Value::Bool(b) => b.to_string(),
+            Value::List(items) => format!("{} items", items.len()),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Str(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Str(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+/// Variables available while rendering a template
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    vars: HashMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<Value>) {
+        self.vars.insert(name.to_string(), value.into());
+    }
+
+    pub fn set_list(&mut self, name: &str, items: Vec<Context>) {
+        self.vars.insert(name.to_string(), Value::List(items));
+    }
+}
+
+/// Result of rendering a template
+#[derive(Debug)]
+pub struct Rendered {
+    pub output: String,
+    /// Variables referenced by the template that weren't defined
+    pub unknown: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var(String),
+    If {
+        name: String,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Each {
+        name: String,
+        body: Vec<Node>,
+    },
+}
+
+enum Tag<'a> {
+    Var(&'a str),
+    If(&'a str),
+    Unless(&'a str),
+    Each(&'a str),
+    Else,
+    End(&'a str),
+}
+
+fn parse_tag(raw: &str) -> Result<Tag<'_>> {
+    let tag = raw.trim();
+
+    if let Some(rest) = tag.strip_prefix('#') {
+        let (kind, name) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let name = name.trim();
+        if name.is_empty() {
+            bail!("Missing variable name in '{{{{{}}}}}'", tag);
+        }
+        return match kind {
+            "if" => Ok(Tag::If(name)),
+            "unless" => Ok(Tag::Unless(name)),
+            "each" => Ok(Tag::Each(name)),
+            _ => bail!("Unknown block '{{{{#{}}}}}'", kind),
+        };
+    }
+
+    if let Some(kind) = tag.strip_prefix('/') {
+        return Ok(Tag::End(kind.trim()));
+    }
+
+    if tag == "else" {
+        return Ok(Tag::Else);
+    }
+
+    if tag.is_empty() {
+        bail!("Empty tag '{{{{}}}}'");
+    }
+
+    Ok(Tag::Var(tag))
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl Parser<'_> {
+    /// Parse nodes until a closing tag (or end of input when `block` is None).
+    /// Returns the nodes and, for `if`/`unless` blocks, the `else` branch.
+    fn parse_block(&mut self, block: Option<&str>) -> Result<(Vec<Node>, Vec<Node>)> {
+        let mut nodes = Vec::new();
+        let mut otherwise = Vec::new();
+        let mut in_else = false;
+
+        loop {
+            let Some(open) = self.rest.find("{{") else {
+                push_text(current(&mut nodes, &mut otherwise, in_else), self.rest);
+                self.rest = "";
+                if let Some(block) = block {
+                    bail!("Unclosed '{{{{#{}}}}}' block", block);
+                }
+                return Ok((nodes, otherwise));
+            };
+
+            push_text(
+                current(&mut nodes, &mut otherwise, in_else),
+                &self.rest[..open],
+            );
+            let after_open = &self.rest[open + 2..];
+            let Some(close) = after_open.find("}}") else {
+                bail!(
+                    "Unclosed tag starting at '{}'",
+                    truncate(&self.rest[open..])
+                );
+            };
+            let raw = &after_open[..close];
+            self.rest = &after_open[close + 2..];
+
+            match parse_tag(raw)? {
+                Tag::Var(name) => {
+                    current(&mut nodes, &mut otherwise, in_else).push(Node::Var(name.to_string()))
+                }
+                Tag::If(name) => {
+                    let node = self.parse_conditional(name, false)?;
+                    current(&mut nodes, &mut otherwise, in_else).push(node);
+                }
+                Tag::Unless(name) => {
+                    let node = self.parse_conditional(name, true)?;
+                    current(&mut nodes, &mut otherwise, in_else).push(node);
+                }
+                Tag::Each(name) => {
+                    let (body, _) = self.parse_block(Some("each"))?;
+                    current(&mut nodes, &mut otherwise, in_else).push(Node::Each {
+                        name: name.to_string(),
+                        body,
+                    });
+                }
+                Tag::Else => match block {
+                    Some("if") | Some("unless") if !in_else => in_else = true,
+                    _ => bail!("Unexpected '{{{{else}}}}'"),
+                },
+                Tag::End(kind) => {
+                    if block == Some(kind) {
+                        return Ok((nodes, otherwise));
+                    }
+                    bail!("Unexpected '{{{{/{}}}}}'", kind);
+                }
+            }
+        }
+    }
+
+    fn parse_conditional(&mut self, name: &str, negate: bool) -> Result<Node> {
+        let (then, otherwise) = self.parse_block(Some(if negate { "unless" } else { "if" }))?;
+        Ok(Node::If {
+            name: name.to_string(),
+            negate,
+            then,
+            otherwise,
+        })
+    }
+}
+
+fn current<'n>(
+    nodes: &'n mut Vec<Node>,
+    otherwise: &'n mut Vec<Node>,
+    in_else: bool,
+) -> &'n mut Vec<Node> {
+    if in_else {
+        otherwise
+    } else {
+        nodes
+    }
+}
+
+fn push_text(nodes: &mut Vec<Node>, text: &str) {
+    if !text.is_empty() {
+        nodes.push(Node::Text(text.to_string()));
+    }
+}
+
+fn truncate(s: &str) -> String {
+    s.chars().take(20).collect()
+}
+
+fn parse(template: &str) -> Result<Vec<Node>> {
+    let mut parser = Parser { rest: template };
+    let (nodes, _) = parser.parse_block(None)?;
+    Ok(nodes)
+}
+
+/// Look up a variable, innermost scope first
+fn lookup<'c>(scopes: &[&'c Context], name: &str) -> Option<&'c Value> {
+    scopes.iter().rev().find_map(|ctx| ctx.vars.get(name))
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    scopes: &mut Vec<&Context>,
+    out: &mut String,
+    unknown: &mut BTreeSet<String>,
+) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Var(name) => match lookup(scopes, name) {
+                Some(value) => out.push_str(&value.render()),
+                None => {
+                    unknown.insert(name.clone());
+                }
+            },
+            Node::If {
+                name,
+                negate,
+                then,
+                otherwise,
+            } => {
+                let truthy = match lookup(scopes, name) {
+                    Some(value) => value.is_truthy(),
+                    None => {
+                        unknown.insert(name.clone());
+                        false
+                    }
+                };
+                let (taken, skipped) = if truthy != *negate {
+                    (then, otherwise)
+                } else {
+                    (otherwise, then)
+                };
+                render_nodes(taken, scopes, out, unknown);
+                // Still walk the other branch so typos there get reported
+                render_nodes(skipped, scopes, &mut String::new(), unknown);
+            }
+            Node::Each { name, body } => match lookup(scopes, name) {
+                Some(Value::List(items)) => {
+                    for item in items {
+                        scopes.push(item);
+                        render_nodes(body, scopes, out, unknown);
+                        scopes.pop();
+                    }
+                }
+                Some(_) => {
+                    unknown.insert(format!("{} (not a list)", name));
+                }
+                None => {
+                    unknown.insert(name.clone());
+                }
+            },
+        }
+    }
+}
+
+/// Render a template against a context.
+///
+/// Supports `{{var}}`, `{{#if var}}…{{else}}…{{/if}}`, `{{#unless var}}…{{/unless}}`
+/// and `{{#each list}}…{{/each}}`. Unknown variables render as empty strings and
+/// are reported in [`Rendered::unknown`].
+pub fn render(template: &str, ctx: &Context) -> Result<Rendered> {
+    let nodes = parse(template)?;
+    let mut out = String::new();
+    let mut unknown = BTreeSet::new();
+    render_nodes(&nodes, &mut vec![ctx], &mut out, &mut unknown);
+
+    Ok(Rendered {
+        output: out,
+        unknown: unknown.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str) -> Context {
+        let mut ctx = Context::new();
+        ctx.set("name", name);
+        ctx
+    }
+
+    fn render_ok(template: &str, ctx: &Context) -> String {
+        render(template, ctx).unwrap().output
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let mut ctx = Context::new();
+        ctx.set("title", "Add login");
+        ctx.set("draft", true);
+        assert_eq!(
+            render_ok("PR: {{ title }} ({{draft}})", &ctx),
+            "PR: Add login (true)"
+        );
+    }
+
+    #[test]
+    fn reports_unknown_variables() {
+        let rendered = render("{{missing}}{{#if other}}{{typo}}{{/if}}", &Context::new()).unwrap();
+        assert_eq!(rendered.output, "");
+        assert_eq!(rendered.unknown, vec!["missing", "other", "typo"]);
+    }
+
+    #[test]
+    fn if_else_picks_branch() {
+        let mut ctx = Context::new();
+        ctx.set("draft", false);
+        ctx.set("empty", "");
+        assert_eq!(render_ok("{{#if draft}}yes{{else}}no{{/if}}", &ctx), "no");
+        assert_eq!(render_ok("{{#if empty}}yes{{else}}no{{/if}}", &ctx), "no");
+        ctx.set("draft", true);
+        assert_eq!(render_ok("{{#if draft}}yes{{else}}no{{/if}}", &ctx), "yes");
+    }
+
+    #[test]
+    fn unless_negates() {
+        let mut ctx = Context::new();
+        ctx.set("draft", false);
+        assert_eq!(
+            render_ok("{{#unless draft}}ready{{else}}wip{{/unless}}", &ctx),
+            "ready"
+        );
+    }
+
+    #[test]
+    fn each_renders_items_with_outer_scope() {
+        let mut ctx = Context::new();
+        ctx.set("prefix", "- ");
+        ctx.set_list("branches", vec![item("a"), item("b")]);
+        assert_eq!(
+            render_ok("{{#each branches}}{{prefix}}{{name}}\n{{/each}}", &ctx),
+            "- a\n- b\n"
+        );
+    }
+
+    #[test]
+    fn each_over_non_list_is_reported() {
+        let mut ctx = Context::new();
+        ctx.set("title", "x");
+        let rendered = render("{{#each title}}{{name}}{{/each}}", &ctx).unwrap();
+        assert_eq!(rendered.unknown, vec!["title (not a list)"]);
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let mut ctx = Context::new();
+        let mut current = item("b");
+        current.set("current", true);
+        ctx.set_list("branches", vec![item("a"), current]);
+        let template = "{{#each branches}}{{#if current}}*{{/if}}{{name}} {{/each}}";
+        assert_eq!(render_ok(template, &ctx), "a *b ");
+    }
+
+    #[test]
+    fn rejects_malformed_templates() {
+        let ctx = Context::new();
+        let error = |template: &str| render(template, &ctx).unwrap_err().to_string();
+        assert_eq!(error("{{#if x}}open"), "Unclosed '{{#if}}' block");
+        assert_eq!(
+            error("{{#each x}}a{{else}}b{{/each}}"),
+            "Unexpected '{{else}}'"
+        );
+        assert_eq!(
+            error("{{#if x}}a{{else}}b{{else}}c{{/if}}"),
+            "Unexpected '{{else}}'"
+        );
+        assert_eq!(error("{{#if x}}a{{/each}}"), "Unexpected '{{/each}}'");
+        assert_eq!(error("text {{name"), "Unclosed tag starting at '{{name'");
+        assert_eq!(error("{{#loop x}}{{/loop}}"), "Unknown block '{{#loop}}'");
+        assert_eq!(
+            error("{{#if}}{{/if}}"),
+            "Missing variable name in '{{#if}}'"
+        );
+        assert_eq!(error("{{ }}"), "Empty tag '{{}}'");
+    }
+}
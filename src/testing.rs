@@ -0,0 +1,71 @@
+use crate::git;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Code under test runs git in the current directory, so fixtures take turns
+static CWD: Mutex<()> = Mutex::new(());
+
+/// A throwaway repository on `main` with one commit, made the current directory
+pub struct Fixture {
+    dir: PathBuf,
+    previous: PathBuf,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let lock = CWD.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = env::temp_dir().join(format!(
+            "gh-flow-test-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::SeqCst)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let previous = env::current_dir().unwrap();
+        env::set_current_dir(&dir).unwrap();
+
+        let fixture = Self {
+            dir,
+            previous,
+            _lock: lock,
+        };
+        fixture.git(&["init", "--quiet", "-b", "main"]);
+        fixture.git(&["config", "user.name", "Test"]);
+        fixture.git(&["config", "user.email", "test@example.com"]);
+        fixture.commit("init");
+        fixture
+    }
+
+    pub fn git(&self, args: &[&str]) -> String {
+        git::run(args).unwrap()
+    }
+
+    /// Commit on the current branch and return its hash
+    pub fn commit(&self, message: &str) -> String {
+        self.git(&["commit", "--quiet", "--allow-empty", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// Write `contents` to `path` and commit it
+    pub fn commit_file(&self, path: &str, contents: &str) -> String {
+        fs::write(path, contents).unwrap();
+        self.git(&["add", path]);
+        self.commit(&format!("Write {}", path))
+    }
+
+    pub fn branch(&self, name: &str) {
+        self.git(&["checkout", "--quiet", "-b", name]);
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = env::set_current_dir(&self.previous);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
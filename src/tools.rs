@@ -0,0 +1,509 @@
+use crate::git;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Stacking tools whose metadata can be imported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Tool {
+    /// Branch metadata refs (or the legacy cache files) in .git
+    Graphite,
+    /// `git-town-branch.<branch>.parent` git config keys
+    GitTown,
+    /// `commit-id:` trailers and `spr/<trunk>/<id>` branches
+    Spr,
+    /// `Pull Request resolved:` trailers and `gh/<user>/<n>/orig` branches
+    Ghstack,
+    /// Branches on the commit graph above the main branch
+    GitBranchless,
+}
+
+/// Stacking tools the stack can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportTool {
+    GitTown,
+}
+
+/// Branch relationships read from another tool
+#[derive(Debug, Default)]
+pub struct Imported {
+    pub trunk: String,
+    /// `(branch, parent)` in the order the tool lists them
+    pub parents: Vec<(String, String)>,
+    pub pr_numbers: HashMap<String, u32>,
+}
+
+impl Imported {
+    fn new(trunk: String) -> Self {
+        Self {
+            trunk,
+            ..Default::default()
+        }
+    }
+}
+
+impl Tool {
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Read the stacks another tool tracks in this repository. For tools that
+/// track commits rather than branches (spr, ghstack), a local branch is
+/// created for each commit of the current stack.
+pub fn read(tool: Tool) -> Result<Imported> {
+    let imported = match tool {
+        Tool::Graphite => read_graphite()?,
+        Tool::GitTown => read_git_town()?,
+        Tool::Spr => read_spr()?,
+        Tool::Ghstack => read_ghstack()?,
+        Tool::GitBranchless => read_git_branchless()?,
+    };
+
+    if imported.parents.is_empty() {
+        anyhow::bail!("No {} stack metadata found in this repository", tool.name());
+    }
+    Ok(imported)
+}
+
+fn git_dir() -> Result<PathBuf> {
+    Ok(PathBuf::from(git::run(&["rev-parse", "--git-common-dir"])?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphiteBranch {
+    parent_branch_name: Option<String>,
+    #[serde(default)]
+    pr_info: Option<GraphitePrInfo>,
+}
+
+#[derive(Deserialize)]
+struct GraphitePrInfo {
+    number: Option<u32>,
+}
+
+fn read_graphite() -> Result<Imported> {
+    let git_dir = git_dir()?;
+
+    #[derive(Deserialize)]
+    struct RepoConfig {
+        trunk: Option<String>,
+    }
+    let trunk = fs::read_to_string(git_dir.join(".graphite_repo_config"))
+        .ok()
+        .and_then(|content| serde_json::from_str::<RepoConfig>(&content).ok())
+        .and_then(|config| config.trunk)
+        .unwrap_or_else(|| "main".to_string());
+    let mut imported = Imported::new(trunk);
+
+    // Current Graphite keeps one JSON blob per branch under refs/branch-metadata/
+    let refs = git::run(&[
+        "for-each-ref",
+        "--format=%(refname)",
+        "refs/branch-metadata/",
+    ])?;
+    for refname in refs.lines() {
+        let branch = refname.trim_start_matches("refs/branch-metadata/");
+        let content = git::run(&["cat-file", "-p", refname])?;
+        let meta: GraphiteBranch = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse Graphite metadata of {}", branch))?;
+        if let Some(parent) = meta.parent_branch_name {
+            imported.parents.push((branch.to_string(), parent));
+        }
+        if let Some(number) = meta.pr_info.and_then(|info| info.number) {
+            imported.pr_numbers.insert(branch.to_string(), number);
+        }
+    }
+    if !imported.parents.is_empty() {
+        return Ok(imported);
+    }
+
+    // Older versions cached the branch graph in a file
+    #[derive(Deserialize)]
+    struct Cache {
+        branches: Vec<(String, GraphiteBranch)>,
+    }
+    if let Ok(content) = fs::read_to_string(git_dir.join(".graphite_cache_persist")) {
+        let cache: Cache =
+            serde_json::from_str(&content).context("Failed to parse .graphite_cache_persist")?;
+        for (branch, meta) in cache.branches {
+            if let Some(parent) = meta.parent_branch_name {
+                imported.parents.push((branch, parent));
+            }
+        }
+    }
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PrInfo {
+        pr_number: u32,
+        head_ref_name: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct PrInfoFile {
+        pr_infos: Vec<PrInfo>,
+    }
+    if let Ok(content) = fs::read_to_string(git_dir.join(".graphite_pr_info")) {
+        if let Ok(file) = serde_json::from_str::<PrInfoFile>(&content) {
+            for info in file.pr_infos {
+                imported
+                    .pr_numbers
+                    .insert(info.head_ref_name, info.pr_number);
+            }
+        }
+    }
+
+    Ok(imported)
+}
+
+fn git_town_trunk() -> String {
+    git::config_get("git-town.main-branch")
+        .or_else(|| git::config_get("git-town.main-branch-name"))
+        .unwrap_or_else(|| "main".to_string())
+}
+
+fn read_git_town() -> Result<Imported> {
+    let mut imported = Imported::new(git_town_trunk());
+
+    // Exits with an error when no key matches
+    let output =
+        git::run(&["config", "--get-regexp", r"^git-town-branch\..*\.parent$"]).unwrap_or_default();
+    for line in output.lines() {
+        let Some((key, parent)) = line.split_once(' ') else {
+            continue;
+        };
+        let branch = key
+            .trim_start_matches("git-town-branch.")
+            .trim_end_matches(".parent");
+        imported
+            .parents
+            .push((branch.to_string(), parent.to_string()));
+    }
+
+    Ok(imported)
+}
+
+/// Value of a `key: value` trailer in a commit body
+fn trailer<'a>(body: &'a str, key: &str) -> Option<&'a str> {
+    body.lines()
+        .filter_map(|line| line.trim().strip_prefix(key))
+        .filter_map(|rest| rest.strip_prefix(':'))
+        .map(str::trim)
+        .next_back()
+}
+
+/// Point a local branch at `sha`, creating it if needed. Refuses to move a
+/// branch that points somewhere else.
+fn ensure_branch(branch: &str, sha: &str) -> Result<()> {
+    match git::rev_parse(branch) {
+        None => {
+            git::run(&["branch", branch, sha])?;
+            Ok(())
+        }
+        Some(existing) if existing == sha => Ok(()),
+        Some(_) => anyhow::bail!(
+            "Branch '{}' already exists and points to a different commit",
+            branch
+        ),
+    }
+}
+
+fn read_spr() -> Result<Imported> {
+    // spr reads its settings from .spr.yml at the repository root
+    let root = git::run(&["rev-parse", "--show-toplevel"])?;
+    let setting = |key: &str| -> Option<String> {
+        let content = fs::read_to_string(PathBuf::from(&root).join(".spr.yml")).ok()?;
+        content
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(key)?.strip_prefix(':'))
+            .map(|value| value.trim().trim_matches('"').to_string())
+    };
+    let trunk = setting("githubBranch").unwrap_or_else(|| "main".to_string());
+    let mut imported = Imported::new(trunk.clone());
+
+    // Each commit is one PR, pushed as spr/<trunk>/<commit-id>
+    let mut parent = trunk.clone();
+    for commit in git::commit_messages(&format!("{}..HEAD", trunk))? {
+        let Some(id) = trailer(&commit.body, "commit-id") else {
+            continue;
+        };
+        let branch = format!("spr/{}/{}", trunk, id);
+        ensure_branch(&branch, &commit.sha)?;
+        imported.parents.push((branch.clone(), parent));
+        parent = branch;
+    }
+
+    Ok(imported)
+}
+
+/// Default branch of the upstream repository as recorded by `git clone`
+/// (`<remote>/HEAD`), else whichever of main and master exists
+fn default_branch() -> String {
+    let remote = git::upstream_remote();
+    let head = git::run(&[
+        "symbolic-ref",
+        "--quiet",
+        "--short",
+        &format!("refs/remotes/{}/HEAD", remote),
+    ]);
+    if let Some(branch) = head
+        .ok()
+        .as_deref()
+        .and_then(|head| head.strip_prefix(&format!("{}/", remote)))
+    {
+        return branch.to_string();
+    }
+    ["main", "master"]
+        .into_iter()
+        .find(|branch| git::rev_parse(branch).is_some())
+        .unwrap_or("main")
+        .to_string()
+}
+
+fn read_ghstack() -> Result<Imported> {
+    // ghstack stacks on the repository's default branch and doesn't record it
+    let trunk = default_branch();
+    let mut imported = Imported::new(trunk.clone());
+    let remote = git::push_remote();
+
+    // ghstack pushes every commit as-is to gh/<user>/<n>/orig; its PRs are opened
+    // from gh/<user>/<n>/head. Importing moves the head branch to the commit, so
+    // the next push turns the PR into a regular stacked PR.
+    let prefix = format!("refs/remotes/{}/", remote);
+    let refs = git::run(&[
+        "for-each-ref",
+        "--format=%(objectname) %(refname)",
+        &format!("{}gh/", prefix),
+    ])?;
+    let orig_branches: HashMap<&str, &str> = refs
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, refname)| refname.ends_with("/orig"))
+        .map(|(sha, refname)| (sha, refname.trim_start_matches(prefix.as_str())))
+        .collect();
+
+    let mut parent = trunk.clone();
+    for commit in git::commit_messages(&format!("{}..HEAD", trunk))? {
+        let Some(orig) = orig_branches.get(commit.sha.as_str()) else {
+            continue;
+        };
+        let branch = format!("{}/head", orig.trim_end_matches("/orig"));
+        ensure_branch(&branch, &commit.sha)?;
+
+        let pr_number = trailer(&commit.body, "Pull Request resolved")
+            .and_then(|url| url.rsplit('/').next())
+            .and_then(|number| number.parse().ok());
+        if let Some(number) = pr_number {
+            imported.pr_numbers.insert(branch.clone(), number);
+        }
+        imported.parents.push((branch.clone(), parent));
+        parent = branch;
+    }
+
+    Ok(imported)
+}
+
+fn read_git_branchless() -> Result<Imported> {
+    // git-branchless has no parent metadata of its own: stacks are the branches
+    // along the commit graph above the main branch
+    let trunk = git::config_get("branchless.core.mainBranch").unwrap_or_else(|| "main".to_string());
+    let mut imported = Imported::new(trunk.clone());
+
+    let branches = git::run(&[
+        "for-each-ref",
+        "--format=%(refname:short) %(objectname)",
+        "refs/heads/",
+    ])?;
+    // Several branches can point at the same commit
+    let mut branches_at: HashMap<String, Vec<String>> = HashMap::new();
+    for line in branches.lines() {
+        if let Some((branch, sha)) = line.split_once(' ') {
+            if branch != trunk {
+                branches_at
+                    .entry(sha.to_string())
+                    .or_default()
+                    .push(branch.to_string());
+            }
+        }
+    }
+
+    let mut parent = trunk.clone();
+    for commit in git::commit_messages(&format!("{}..HEAD", trunk))? {
+        let Some(branches) = branches_at.get_mut(&commit.sha) else {
+            continue;
+        };
+        // Stack them in name order; all but the first have no commits of their own
+        branches.sort();
+        for branch in branches.iter() {
+            imported.parents.push((branch.clone(), parent));
+            parent = branch.clone();
+        }
+    }
+
+    Ok(imported)
+}
+
+/// Record the stack as git-town parent branches
+pub fn export_git_town(base_branch: &str, branches: &[(String, String)]) -> Result<()> {
+    if git::config_get("git-town.main-branch").is_none()
+        && git::config_get("git-town.main-branch-name").is_none()
+    {
+        git::run(&["config", "git-town.main-branch", base_branch])?;
+    }
+    for (branch, parent) in branches {
+        git::run(&[
+            "config",
+            &format!("git-town-branch.{}.parent", branch),
+            parent,
+        ])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Fixture;
+
+    fn pairs(imported: &Imported) -> Vec<(&str, &str)> {
+        imported
+            .parents
+            .iter()
+            .map(|(branch, parent)| (branch.as_str(), parent.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn graphite_reads_branch_metadata_refs() {
+        let repo = Fixture::new();
+        fs::write(".git/.graphite_repo_config", r#"{"trunk":"main"}"#).unwrap();
+        for (branch, meta) in [
+            ("a", r#"{"parentBranchName":"main","prInfo":{"number":7}}"#),
+            ("b", r#"{"parentBranchName":"a"}"#),
+        ] {
+            let blob = git::run_with_input(&["hash-object", "-w", "--stdin"], meta).unwrap();
+            repo.git(&[
+                "update-ref",
+                &format!("refs/branch-metadata/{}", branch),
+                &blob,
+            ]);
+        }
+
+        let imported = read(Tool::Graphite).unwrap();
+        assert_eq!(imported.trunk, "main");
+        assert_eq!(pairs(&imported), vec![("a", "main"), ("b", "a")]);
+        assert_eq!(imported.pr_numbers.get("a"), Some(&7));
+        assert_eq!(imported.pr_numbers.get("b"), None);
+    }
+
+    #[test]
+    fn graphite_falls_back_to_the_legacy_cache() {
+        let _repo = Fixture::new();
+        fs::write(".git/.graphite_repo_config", r#"{"trunk":"develop"}"#).unwrap();
+        fs::write(
+            ".git/.graphite_cache_persist",
+            r#"{"branches":[["a",{"parentBranchName":"develop"}],["develop",{}]]}"#,
+        )
+        .unwrap();
+        fs::write(
+            ".git/.graphite_pr_info",
+            r#"{"prInfos":[{"prNumber":3,"headRefName":"a"}]}"#,
+        )
+        .unwrap();
+
+        let imported = read(Tool::Graphite).unwrap();
+        assert_eq!(imported.trunk, "develop");
+        assert_eq!(pairs(&imported), vec![("a", "develop")]);
+        assert_eq!(imported.pr_numbers.get("a"), Some(&3));
+    }
+
+    #[test]
+    fn git_town_reads_parent_config() {
+        let repo = Fixture::new();
+        repo.git(&["config", "git-town.main-branch", "main"]);
+        repo.git(&["config", "git-town-branch.feature/a.parent", "main"]);
+        repo.git(&["config", "git-town-branch.feature/b.parent", "feature/a"]);
+
+        let imported = read(Tool::GitTown).unwrap();
+        assert_eq!(imported.trunk, "main");
+        assert_eq!(
+            pairs(&imported),
+            vec![("feature/a", "main"), ("feature/b", "feature/a")]
+        );
+    }
+
+    #[test]
+    fn spr_creates_a_branch_per_commit_id() {
+        let repo = Fixture::new();
+        repo.branch("work");
+        let first = repo.commit("First\n\ncommit-id: aaaa1111");
+        repo.commit("No trailer");
+        let second = repo.commit("Second\n\ncommit-id: bbbb2222");
+
+        let imported = read(Tool::Spr).unwrap();
+        assert_eq!(
+            pairs(&imported),
+            vec![
+                ("spr/main/aaaa1111", "main"),
+                ("spr/main/bbbb2222", "spr/main/aaaa1111")
+            ]
+        );
+        assert_eq!(git::rev_parse("spr/main/aaaa1111"), Some(first));
+        assert_eq!(git::rev_parse("spr/main/bbbb2222"), Some(second));
+    }
+
+    #[test]
+    fn ghstack_detects_the_trunk_and_reads_pr_numbers() {
+        let repo = Fixture::new();
+        repo.git(&["branch", "-m", "main", "master"]);
+        repo.git(&["update-ref", "refs/remotes/origin/master", "master"]);
+        repo.git(&[
+            "symbolic-ref",
+            "refs/remotes/origin/HEAD",
+            "refs/remotes/origin/master",
+        ]);
+        repo.branch("work");
+        let first = repo.commit("First\n\nPull Request resolved: https://github.com/o/r/pull/11");
+        let second = repo.commit("Second\n\nPull Request resolved: https://github.com/o/r/pull/12");
+        repo.git(&["update-ref", "refs/remotes/origin/gh/me/1/orig", &first]);
+        repo.git(&["update-ref", "refs/remotes/origin/gh/me/2/orig", &second]);
+
+        let imported = read(Tool::Ghstack).unwrap();
+        assert_eq!(imported.trunk, "master");
+        assert_eq!(
+            pairs(&imported),
+            vec![("gh/me/1/head", "master"), ("gh/me/2/head", "gh/me/1/head")]
+        );
+        assert_eq!(imported.pr_numbers.get("gh/me/2/head"), Some(&12));
+        assert_eq!(git::rev_parse("gh/me/1/head"), Some(first));
+    }
+
+    #[test]
+    fn git_branchless_keeps_branches_sharing_a_commit() {
+        let repo = Fixture::new();
+        repo.branch("a");
+        repo.commit("a1");
+        repo.git(&["branch", "a-copy"]);
+        repo.branch("b");
+        repo.commit("b1");
+
+        let imported = read(Tool::GitBranchless).unwrap();
+        assert_eq!(
+            pairs(&imported),
+            vec![("a", "main"), ("a-copy", "a"), ("b", "a-copy")]
+        );
+    }
+
+    #[test]
+    fn read_fails_without_metadata() {
+        let _repo = Fixture::new();
+        assert!(read(Tool::GitTown).is_err());
+    }
+}
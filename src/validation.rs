@@ -1,38 +1,62 @@
-use anyhow::{bail, Result};
+use crate::error::FlowError;
+use crate::{git, github};
+use anyhow::Result;
 use colored::*;
+use std::path::Path;
 use std::process::Command;
 
-#[allow(dead_code)]
+/// Conditions a command needs before it starts, checked up front so it
+/// doesn't fail halfway through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    GitRepo,
+    /// gh is installed and logged in
+    GhAuth,
+    /// HEAD is a branch
+    OnBranch,
+    NoRebaseInProgress,
+}
+
+/// Run the checks a command declared, in order
+pub fn preflight(checks: &[Check]) -> Result<()> {
+    for check in checks {
+        match check {
+            Check::GitRepo => check_git_repo()?,
+            Check::GhAuth => check_gh_auth()?,
+            Check::OnBranch => check_on_branch()?,
+            Check::NoRebaseInProgress => check_no_rebase_in_progress()?,
+        }
+    }
+    Ok(())
+}
+
 pub fn check_git_repo() -> Result<()> {
     let output = Command::new("git")
         .args(["rev-parse", "--is-inside-work-tree"])
-        .output()?;
+        .output()
+        .map_err(|_| FlowError::GitMissing)?;
 
     if !output.status.success() {
-        bail!(
-            "{} Not a git repository\n\n{}\n  Run this command inside a git repository",
-            "Error:".red().bold(),
-            "Suggestion:".yellow()
-        );
+        return Err(FlowError::NotARepo.into());
     }
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn check_gh_auth() -> Result<()> {
-    let output = Command::new("gh").args(["auth", "status"]).output()?;
+    let mut cmd = Command::new("gh");
+    cmd.args(["auth", "status"]);
+    // Only the host the stack's repository lives on matters
+    if let Some(host) = github::host() {
+        cmd.args(["--hostname", &host]);
+    }
+    let output = cmd.output().map_err(|_| FlowError::GhMissing)?;
 
     if !output.status.success() {
-        bail!(
-            "{} GitHub CLI not authenticated\n\n{}\n  Run: gh auth login",
-            "Error:".red().bold(),
-            "Suggestion:".yellow()
-        );
+        return Err(FlowError::Unauthenticated.into());
     }
     Ok(())
 }
 
-#[allow(dead_code)]
 pub fn check_uncommitted_changes() -> Result<bool> {
     let output = Command::new("git")
         .args(["status", "--porcelain"])
@@ -41,7 +65,6 @@ pub fn check_uncommitted_changes() -> Result<bool> {
     Ok(!output.stdout.is_empty())
 }
 
-#[allow(dead_code)]
 pub fn warn_uncommitted_changes() -> Result<()> {
     if check_uncommitted_changes()? {
         println!(
@@ -52,6 +75,31 @@ pub fn warn_uncommitted_changes() -> Result<()> {
     Ok(())
 }
 
+/// Untracked files don't get in the way of a rebase, so only tracked changes count
+pub fn check_clean_worktree() -> Result<()> {
+    if !git::run(&["status", "--porcelain", "--untracked-files=no"])?.is_empty() {
+        return Err(FlowError::DirtyWorktree.into());
+    }
+    Ok(())
+}
+
+pub fn check_on_branch() -> Result<()> {
+    if git::run(&["symbolic-ref", "--quiet", "HEAD"]).is_err() {
+        return Err(FlowError::DetachedHead.into());
+    }
+    Ok(())
+}
+
+pub fn check_no_rebase_in_progress() -> Result<()> {
+    for dir in ["rebase-merge", "rebase-apply"] {
+        let path = git::run(&["rev-parse", "--git-path", dir])?;
+        if Path::new(&path).exists() {
+            return Err(FlowError::RebaseInProgress.into());
+        }
+    }
+    Ok(())
+}
+
 #[allow(dead_code)]
 pub fn check_branch_exists(branch: &str) -> Result<bool> {
     let output = Command::new("git")
@@ -0,0 +1,85 @@
+use crate::git;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SCRATCH_DIR: &str = "gh-flow-scratch";
+
+/// A detached worktree inside the git directory for rebasing branches without
+/// touching the working tree the user is in. Removed on drop.
+pub struct Scratch {
+    path: PathBuf,
+}
+
+impl Scratch {
+    pub fn create() -> Result<Self> {
+        let path = PathBuf::from(git::run(&[
+            "rev-parse",
+            "--path-format=absolute",
+            "--git-path",
+            SCRATCH_DIR,
+        ])?);
+        // Left behind by a run that was killed
+        if path.exists() {
+            let _ = git::run(&["worktree", "remove", "--force", &path.to_string_lossy()]);
+            let _ = fs::remove_dir_all(&path);
+            let _ = git::run(&["worktree", "prune"]);
+        }
+
+        git::run(&[
+            "worktree",
+            "add",
+            "--detach",
+            "--quiet",
+            &path.to_string_lossy(),
+            "HEAD",
+        ])
+        .context("Failed to create a scratch worktree")?;
+        Ok(Self { path })
+    }
+
+    /// Replay the commits of `tip` after `fork_point` onto `onto` and return the
+    /// new tip. No branch is updated. On a conflict the rebase is abandoned.
+    pub fn rebase(&self, tip: &str, fork_point: &str, onto: &str) -> Result<String> {
+        git::run_in(&self.path, &["checkout", "--detach", "--quiet", tip])?;
+        if let Err(e) = git::run_in(
+            &self.path,
+            &["rebase", "--quiet", "--onto", onto, fork_point],
+        ) {
+            let _ = git::run_in(&self.path, &["rebase", "--abort"]);
+            return Err(e);
+        }
+        git::run_in(&self.path, &["rev-parse", "HEAD"])
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = git::run(&[
+            "worktree",
+            "remove",
+            "--force",
+            &self.path.to_string_lossy(),
+        ]);
+    }
+}
+
+/// Branches checked out in worktrees other than the current one, and where
+pub fn checked_out_elsewhere() -> Result<HashMap<String, PathBuf>> {
+    let current = PathBuf::from(git::run(&["rev-parse", "--show-toplevel"])?);
+    let output = git::run(&["worktree", "list", "--porcelain"])?;
+
+    let mut branches = HashMap::new();
+    let mut path: Option<&Path> = None;
+    for line in output.lines() {
+        if let Some(worktree) = line.strip_prefix("worktree ") {
+            path = Some(Path::new(worktree));
+        } else if let (Some(branch), Some(path)) = (line.strip_prefix("branch refs/heads/"), path) {
+            if path != current {
+                branches.insert(branch.to_string(), path.to_path_buf());
+            }
+        }
+    }
+    Ok(branches)
+}